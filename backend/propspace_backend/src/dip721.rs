@@ -23,7 +23,7 @@ impl Into<String> for CanisterError {
     }
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone)]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum NftError {
     SelfTransfer,
     TokenNotFound,
@@ -37,6 +37,7 @@ pub enum NftError {
     UnitsNotAvailable,
     InsufficientUnits,
     SenderNotOwner,
+    NotVerified,
     Other(String),
 }
 
@@ -151,6 +152,13 @@ struct Space {
     num_units_available: u64,
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct SpaceHolding {
+    pub token_id: u64,
+    pub owner: Principal,
+    pub num_units: u64,
+}
+
 type CanisterResult<T = ()> = Result<T, String>;
 pub type NftResult<T = ()> = Result<T, NftError>;
 
@@ -171,24 +179,51 @@ impl DIP721Service {
         space_id: u64,
         space_details: SpaceDetails,
         properties: Option<Vec<(String, GenericValue)>>,
-        token_data: u64,
         num_units: u64,
-    ) -> Result<(), DaoServiceError> {
+    ) -> Result<TokenIdentifier, DaoServiceError> {
         let token_data = TokenData {
             bytes: serialize(&space_details).unwrap(),
             data_type: DataType::Raw,
         };
 
-        let minted_token_result: Result<(NftResult,), _> = ic_cdk::call(
+        let minted_token_result: Result<(NftResult<TokenIdentifier>,), _> = ic_cdk::call(
             self.principal,
             "mintHouse",
             (owner, properties, space_id, token_data, num_units),
         )
         .await;
 
-        match (minted_token_result) {
+        match minted_token_result {
             Ok(res) => match res.0 {
-                Ok(id) => return Ok(()),
+                Ok(id) => Ok(id),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    //a principal may only trade units until the given time, none revokes it
+    pub async fn set_verified_until(
+        &self,
+        principal: Principal,
+        verified_until: Option<u64>,
+    ) -> Result<(), DaoServiceError> {
+        let verified_result: Result<(NftResult,), _> = ic_cdk::call(
+            self.principal,
+            "setVerifiedUntil",
+            (principal, verified_until),
+        )
+        .await;
+
+        match verified_result {
+            Ok(res) => match res.0 {
+                Ok(()) => Ok(()),
 
                 Err(err) => Err(DaoServiceError {
                     error_type: ErrorType::NftError(err),
@@ -229,4 +264,125 @@ impl DIP721Service {
             }),
         }
     }
+
+    pub async fn set_space_price(
+        &self,
+        space_id: u64,
+        price_per_unit: u64,
+    ) -> Result<(), DaoServiceError> {
+        let set_price_result: Result<(NftResult,), _> =
+            ic_cdk::call(self.principal, "setSpacePrice", (space_id, price_per_unit)).await;
+
+        match set_price_result {
+            Ok(res) => match res.0 {
+                Ok(()) => Ok(()),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    pub async fn is_approved_for_all(
+        &self,
+        owner: Principal,
+        operator: Principal,
+    ) -> Result<bool, DaoServiceError> {
+        let approved_result: Result<(NftResult<bool>,), _> =
+            ic_cdk::call(self.principal, "isApprovedForAll", (owner, operator)).await;
+
+        match approved_result {
+            Ok(res) => match res.0 {
+                Ok(approved) => Ok(approved),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    pub async fn operator_of(
+        &self,
+        token_id: TokenIdentifier,
+    ) -> Result<Option<Principal>, DaoServiceError> {
+        let operator_result: Result<(NftResult<Option<Principal>>,), _> =
+            ic_cdk::call(self.principal, "operatorOf", (Nat::from(token_id),)).await;
+
+        match operator_result {
+            Ok(res) => match res.0 {
+                Ok(operator) => Ok(operator),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    //live tokens of the space, in token id order
+    pub async fn get_space_holdings(
+        &self,
+        space_id: u64,
+    ) -> Result<Vec<SpaceHolding>, DaoServiceError> {
+        let holdings_result: Result<(NftResult<Vec<SpaceHolding>>,), _> =
+            ic_cdk::call(self.principal, "getSpaceHoldings", (space_id,)).await;
+
+        match holdings_result {
+            Ok(res) => match res.0 {
+                Ok(holdings) => Ok(holdings),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    //registers a space in the dip721 canister and returns the id it was stored under
+    pub async fn create_space(
+        &self,
+        price_per_unit: u64,
+        num_units_available: u64,
+    ) -> Result<u64, DaoServiceError> {
+        let space = Space {
+            id: 0,
+            price_per_unit,
+            num_units_available,
+        };
+
+        let created_space_result: Result<(NftResult<Space>,), _> =
+            ic_cdk::call(self.principal, "createSpace", (space,)).await;
+
+        match created_space_result {
+            Ok(res) => match res.0 {
+                Ok(space) => Ok(space.id),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::NftError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
 }
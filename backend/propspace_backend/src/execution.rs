@@ -0,0 +1,46 @@
+use crate::dip721::DIP721Service;
+use crate::types::*;
+use crate::SERVICE;
+
+//carries out the proposition of an accepted proposal against the dip721 canister
+//the service is only borrowed around the inter-canister call, never across it
+pub async fn execute_proposal(proposal_id: u64) -> Result<ProposalState, DaoServiceError> {
+    let (proposal, dip_service) = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let proposal = service.begin_execution(proposal_id)?;
+        Ok::<_, DaoServiceError>((proposal, DIP721Service::from(service.dip_service_principal)))
+    })?;
+
+    let result = match &proposal.proposition {
+        Proposition::UnitsSale(sale) => {
+            //either party may have lost their verification while the proposal was open
+            let verified = SERVICE.with(|service| {
+                let service = service.borrow();
+                service
+                    .require_verified(&proposal.proposer)
+                    .and_then(|_| service.require_verified(&sale.buyer_account))
+            });
+            match verified {
+                Ok(()) => {
+                    dip_service
+                        .trade_units(
+                            sale.token_id,
+                            proposal.proposer,
+                            sale.buyer_account,
+                            sale.num_units,
+                        )
+                        .await
+                }
+                Err(err) => Err(err),
+            }
+        }
+        Proposition::SetPrice(price) => {
+            dip_service
+                .set_space_price(proposal.housing_unit, price.new_price)
+                .await
+        }
+        Proposition::Other(_) => Ok(()),
+    };
+
+    Ok(SERVICE.with(|service| service.borrow_mut().complete_execution(proposal_id, result)))
+}
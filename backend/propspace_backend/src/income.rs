@@ -0,0 +1,111 @@
+use crate::dip721::DIP721Service;
+use crate::payments::PaymentsService;
+use crate::service::split_income;
+use crate::types::*;
+use crate::SERVICE;
+
+//collects the amount plus the fee of every payout from the caller, who must have approved this
+//canister for it, then pays each live token of the space its share
+pub async fn distribute_income(
+    space_id: u64,
+    amount_e8s: u64,
+) -> Result<Vec<Payout>, DaoServiceError> {
+    let (distribution_id, caller, treasury, dip_service, payments_service) =
+        SERVICE.with(|service| {
+            let mut service = service.borrow_mut();
+            let distribution_id = service.begin_distribution(space_id, amount_e8s)?;
+            Ok::<_, DaoServiceError>((
+                distribution_id,
+                service.env.caller(),
+                service.env.canister_id(),
+                DIP721Service::from(service.dip_service_principal),
+                PaymentsService::from(service.payments_service_principal),
+            ))
+        })?;
+
+    let holdings = dip_service.get_space_holdings(space_id).await?;
+    let shares = match split_income(amount_e8s, &holdings) {
+        Some(shares) => shares,
+        None => {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("space has no holders")),
+            })
+        }
+    };
+
+    let fee = payments_service.fee().await?;
+    let num_payouts = shares.iter().filter(|share| **share > 0).count() as u64;
+    let total = match fee
+        .checked_mul(num_payouts)
+        .and_then(|fees| fees.checked_add(amount_e8s))
+    {
+        Some(total) => total,
+        None => {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("distribution amount overflows")),
+            })
+        }
+    };
+    payments_service
+        .transfer_from(caller, treasury, total, None)
+        .await?;
+
+    //every payout is recorded as pending before any is paid so an interrupted distribution
+    //still shows what each token is owed
+    let timestamp = ic_cdk::api::time();
+    let payouts: Vec<Payout> = holdings
+        .iter()
+        .zip(shares)
+        .filter(|(_, share)| *share > 0)
+        .map(|(holding, share)| Payout {
+            distribution_id,
+            space_id,
+            token_id: holding.token_id,
+            owner: holding.owner,
+            num_units: holding.num_units,
+            amount_e8s: share,
+            timestamp,
+            status: PayoutStatus::Pending,
+        })
+        .collect();
+    SERVICE.with(|service| service.borrow_mut().record_payouts(&payouts));
+
+    Ok(pay(&payments_service, payouts).await)
+}
+
+//pays the failed payouts of a distribution again, returns them with their new status
+pub async fn retry_payouts(distribution_id: u64) -> Result<Vec<Payout>, DaoServiceError> {
+    let (payouts, payments_service) = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let payouts = service.take_failed_payouts(distribution_id)?;
+        Ok::<_, DaoServiceError>((
+            payouts,
+            PaymentsService::from(service.payments_service_principal),
+        ))
+    })?;
+
+    Ok(pay(&payments_service, payouts).await)
+}
+
+//each payout is settled as soon as its transfer returns
+async fn pay(payments_service: &PaymentsService, mut payouts: Vec<Payout>) -> Vec<Payout> {
+    for payout in payouts.iter_mut() {
+        let memo = Some(payout.distribution_id.to_be_bytes().to_vec());
+        payout.status = match payments_service
+            .transfer(payout.owner, payout.amount_e8s, memo)
+            .await
+        {
+            Ok(block_index) => PayoutStatus::Paid { block_index },
+            Err(err) => PayoutStatus::Failed(format!("{:?}", err.error_type)),
+        };
+        payout.timestamp = ic_cdk::api::time();
+        SERVICE.with(|service| {
+            service.borrow_mut().settle_payout(
+                payout.distribution_id,
+                payout.token_id,
+                payout.status.clone(),
+            )
+        });
+    }
+    payouts
+}
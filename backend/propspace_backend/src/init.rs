@@ -1,16 +1,67 @@
 use crate::env::CanisterEnvironment;
+use crate::execution;
 use crate::service::HousingDaoService;
-use crate::types::HousingDaoStorage;
+use crate::types::{HousingDaoStorage, Role};
 use crate::SERVICE;
-use ic_cdk_macros::init;
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade};
+use std::collections::HashSet;
+use std::time::Duration;
+
+const PROPOSAL_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 #[init]
 fn init(init_state: Option<HousingDaoStorage>) {
     ic_cdk::setup();
 
-    let init_state = init_state.unwrap_or_default();
-    let mut init_service = HousingDaoService::from(init_state);
-    init_service.env = Box::new(CanisterEnvironment {});
+    install(init_state.unwrap_or_default());
+    start_proposal_timer();
+}
+
+fn install(storage: HousingDaoStorage) {
+    let mut service = HousingDaoService::from(storage);
+    service.env = Box::new(CanisterEnvironment {});
+    //whoever installs the canister administers it unless roles were given
+    if service.roles.is_empty() {
+        service
+            .roles
+            .insert(ic_cdk::caller(), HashSet::from([Role::Admin]));
+    }
+
+    SERVICE.with(|s| *s.borrow_mut() = service);
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let storage = SERVICE.with(|service| HousingDaoStorage::from(&*service.borrow()));
+    ic_cdk::storage::stable_save((storage,)).expect("failed to save dao state to stable memory");
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    ic_cdk::setup();
+
+    //releases before the state was persisted leave stable memory empty
+    let storage = if ic_cdk::api::stable::stable64_size() == 0 {
+        HousingDaoStorage::default()
+    } else {
+        let (storage,): (HousingDaoStorage,) = ic_cdk::storage::stable_restore()
+            .expect("failed to restore dao state from stable memory");
+        storage
+    };
+    install(storage);
+
+    //timers do not survive upgrades
+    start_proposal_timer();
+}
 
-    SERVICE.with(|service| *service.borrow_mut() = init_service);
+//periodically closes expired proposals and executes the accepted ones
+pub fn start_proposal_timer() {
+    ic_cdk_timers::set_timer_interval(PROPOSAL_CHECK_INTERVAL, || {
+        let accepted = SERVICE.with(|service| service.borrow_mut().close_expired_proposals());
+        for proposal_id in accepted {
+            ic_cdk::spawn(async move {
+                let _ = execution::execute_proposal(proposal_id).await;
+            });
+        }
+    });
 }
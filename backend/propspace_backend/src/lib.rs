@@ -1,26 +1,49 @@
 use crate::types::*;
 use candid::Principal;
+use dip721::DIP721Service;
 use ic_cdk;
 use ic_cdk::export::candid::candid_method;
 use ic_cdk::export::candid::export_service;
+use payments::PaymentsService;
 use service::HousingDaoService;
 
 use std::cell::RefCell;
 
 mod dip721;
 mod env;
+mod execution;
+mod income;
 mod init;
+mod payments;
+mod proposals;
 mod service;
+mod spaces;
 mod types;
 
 thread_local! {
     static SERVICE: RefCell<HousingDaoService> = RefCell::default();
 }
 
-#[ic_cdk::update]
-#[ic_cdk::export::candid::candid_method]
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
 fn list_spaces() -> Vec<Space> {
-    SERVICE.with(|service| service.borrow_mut().get_all_spaces())
+    SERVICE.with(|service| service.borrow().get_all_spaces())
+}
+
+//pages hold at most 100 spaces, the default sort is by id
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn search_units(
+    filter: SpaceFilter,
+    sort: Option<SpaceSort>,
+    offset: u64,
+    limit: u64,
+) -> SpacePage {
+    SERVICE.with(|service| {
+        service
+            .borrow()
+            .search_units(filter, sort.unwrap_or(SpaceSort::IdAsc), offset, limit)
+    })
 }
 
 #[ic_cdk::query]
@@ -36,32 +59,168 @@ fn get_housing_units_from_space(space_id: u64) -> Result<Vec<HousingUnit>, DaoSe
 }
 #[ic_cdk::update]
 #[ic_cdk::export::candid::candid_method]
-fn create_account(account: Account, secret_key: String) -> Result<Principal, DaoServiceError> {
-    SERVICE.with(|service| service.borrow_mut().create_account(secret_key, account))
+fn create_account() -> Result<Principal, DaoServiceError> {
+    SERVICE.with(|service| service.borrow_mut().create_account())
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn create_space(details: SpaceDetails) -> Result<u64, DaoServiceError> {
+    spaces::create_space(details).await
+}
+
+//returns the id of the dip721 token minted for the units
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn purchase_units(space_id: u64, num_units: u64) -> Result<u64, DaoServiceError> {
+    spaces::purchase_units(space_id, num_units).await
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn get_owed_refunds() -> Result<Vec<OwedRefund>, DaoServiceError> {
+    SERVICE.with(|service| service.borrow().get_owed_refunds())
 }
 
+//returns the refunds that are still owed after the retry
 #[ic_cdk::update]
 #[ic_cdk::export::candid::candid_method]
-fn create_space(details: SpaceDetails) -> Result<u64, DaoServiceError> {
-    SERVICE.with(|service| service.borrow_mut().create_space(details))
+async fn retry_refunds() -> Result<Vec<OwedRefund>, DaoServiceError> {
+    spaces::retry_refunds().await
 }
 
 #[ic_cdk::query]
 #[ic_cdk::export::candid::candid_method(query)]
-fn list_accounts() -> Vec<Account> {
+fn list_accounts() -> Result<Vec<Account>, DaoServiceError> {
     SERVICE.with(|service| service.borrow().list_accounts())
 }
 
+//splits the amount over the space's token holders by units held, returns one payout per holder
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn distribute_income(space_id: u64, amount_e8s: u64) -> Result<Vec<Payout>, DaoServiceError> {
+    income::distribute_income(space_id, amount_e8s).await
+}
+
+//pays the failed payouts of a distribution again, returns them with their new status
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn retry_payouts(distribution_id: u64) -> Result<Vec<Payout>, DaoServiceError> {
+    income::retry_payouts(distribution_id).await
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn get_income_statement() -> IncomeStatement {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        service.income_statement(service.env.caller())
+    })
+}
+
+//the caller's payouts as csv text, one row per payout
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn export_income_statement() -> String {
+    SERVICE.with(|service| {
+        let service = service.borrow();
+        service.income_statement_csv(service.env.caller())
+    })
+}
+
 #[ic_cdk::query]
 #[ic_cdk::export::candid::candid_method(query)]
 fn get_account_details() -> Result<Account, DaoServiceError> {
     SERVICE.with(|service| service.borrow().get_account_details())
 }
 
+//balance of the caller's default account on the payments ledger
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn get_balance() -> Result<Tokens, DaoServiceError> {
+    let payments_service =
+        SERVICE.with(|service| PaymentsService::from(service.borrow().payments_service_principal));
+    payments_service.balance_of(ic_cdk::caller()).await
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn submit_proposal(
+    housing_unit: u64,
+    proposition: Proposition,
+) -> Result<u64, DaoServiceError> {
+    proposals::submit_proposal(housing_unit, proposition).await
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn vote(proposal_id: u64, ballot: Vote) -> Result<ProposalState, DaoServiceError> {
+    proposals::vote(proposal_id, ballot).await
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn execute_proposal(proposal_id: u64) -> Result<ProposalState, DaoServiceError> {
+    execution::execute_proposal(proposal_id).await
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn get_proposal(proposal_id: u64) -> Result<Proposal, DaoServiceError> {
+    SERVICE.with(|service| service.borrow().get_proposal(proposal_id))
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn list_proposals() -> Vec<Proposal> {
+    SERVICE.with(|service| service.borrow().list_proposals())
+}
+
+//auditors record the outcome of identity checks, the dip721 canister is updated first so the
+//backend never records a status the dip721 canister did not accept
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+async fn set_kyc_status(principal: Principal, kyc: KycStatus) -> Result<(), DaoServiceError> {
+    let (verified_until, dip_service) = SERVICE.with(|service| {
+        let service = service.borrow();
+        let verified_until = service.check_kyc_status(&principal, &kyc)?;
+        Ok::<_, DaoServiceError>((
+            verified_until,
+            DIP721Service::from(service.dip_service_principal),
+        ))
+    })?;
+
+    dip_service
+        .set_verified_until(principal, verified_until)
+        .await?;
+
+    SERVICE.with(|service| service.borrow_mut().apply_kyc_status(&principal, kyc));
+    Ok(())
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    SERVICE.with(|service| service.borrow().get_roles(&principal))
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+fn grant_role(principal: Principal, role: Role) -> Result<(), DaoServiceError> {
+    SERVICE.with(|service| service.borrow_mut().grant_role(principal, role))
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+fn revoke_role(principal: Principal, role: Role) -> Result<(), DaoServiceError> {
+    SERVICE.with(|service| service.borrow_mut().revoke_role(principal, role))
+}
+
+//available to auditors and admins
 #[ic_cdk::query]
 #[ic_cdk::export::candid::candid_method(query)]
-fn get_balance() -> Result<Tokens, DaoServiceError> {
-    SERVICE.with(|service| service.borrow().get_balance())
+fn export_storage() -> Result<HousingDaoStorage, DaoServiceError> {
+    SERVICE.with(|service| service.borrow().export_storage())
 }
 
 ic_cdk::export::candid::export_service!();
@@ -0,0 +1,186 @@
+use crate::types::*;
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+
+pub type Subaccount = Vec<u8>;
+
+//account on the payments ledger, not to be confused with a dao account
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct LedgerAccount {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl From<Principal> for LedgerAccount {
+    fn from(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferArg {
+    from_subaccount: Option<Subaccount>,
+    to: LedgerAccount,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Subaccount>,
+    from: LedgerAccount,
+    to: LedgerAccount,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+fn to_u64(amount: &Nat) -> Result<u64, DaoServiceError> {
+    u64::try_from(&amount.0).map_err(|_| DaoServiceError {
+        error_type: ErrorType::CanisterError(String::from("amount does not fit in a u64")),
+    })
+}
+
+pub struct PaymentsService {
+    principal: Principal,
+}
+
+impl From<Principal> for PaymentsService {
+    fn from(principal: Principal) -> Self {
+        Self { principal }
+    }
+}
+
+impl PaymentsService {
+    pub async fn balance_of(&self, owner: Principal) -> Result<Tokens, DaoServiceError> {
+        let balance_result: Result<(Nat,), _> = ic_cdk::call(
+            self.principal,
+            "icrc1_balance_of",
+            (LedgerAccount::from(owner),),
+        )
+        .await;
+
+        match balance_result {
+            Ok(res) => Ok(Tokens {
+                amount_e8s: to_u64(&res.0)?,
+            }),
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    pub async fn fee(&self) -> Result<u64, DaoServiceError> {
+        let fee_result: Result<(Nat,), _> = ic_cdk::call(self.principal, "icrc1_fee", ()).await;
+
+        match fee_result {
+            Ok(res) => to_u64(&res.0),
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    //pays out of the canister's own ledger account
+    pub async fn transfer(
+        &self,
+        to: Principal,
+        amount_e8s: u64,
+        memo: Option<Vec<u8>>,
+    ) -> Result<u64, DaoServiceError> {
+        let arg = TransferArg {
+            from_subaccount: None,
+            to: LedgerAccount::from(to),
+            amount: Nat::from(amount_e8s),
+            fee: None,
+            memo,
+            created_at_time: None,
+        };
+
+        let transfer_result: Result<(Result<Nat, TransferError>,), _> =
+            ic_cdk::call(self.principal, "icrc1_transfer", (arg,)).await;
+
+        match transfer_result {
+            Ok(res) => match res.0 {
+                Ok(block) => to_u64(&block),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::TransferError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+
+    //moves funds the owner approved this canister to spend
+    pub async fn transfer_from(
+        &self,
+        from: Principal,
+        to: Principal,
+        amount_e8s: u64,
+        memo: Option<Vec<u8>>,
+    ) -> Result<u64, DaoServiceError> {
+        let arg = TransferFromArgs {
+            spender_subaccount: None,
+            from: LedgerAccount::from(from),
+            to: LedgerAccount::from(to),
+            amount: Nat::from(amount_e8s),
+            fee: None,
+            memo,
+            created_at_time: None,
+        };
+
+        let transfer_result: Result<(Result<Nat, TransferFromError>,), _> =
+            ic_cdk::call(self.principal, "icrc2_transfer_from", (arg,)).await;
+
+        match transfer_result {
+            Ok(res) => match res.0 {
+                Ok(block) => to_u64(&block),
+
+                Err(err) => Err(DaoServiceError {
+                    error_type: ErrorType::TransferFromError(err),
+                }),
+            },
+
+            Err(err) => Err(DaoServiceError {
+                error_type: ErrorType::CanisterError(err.1),
+            }),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::dip721::DIP721Service;
+use crate::execution;
+use crate::types::*;
+use crate::SERVICE;
+
+//voting weight comes from the space's dip721 tokens, fetched before the service is borrowed
+pub async fn submit_proposal(
+    housing_unit: u64,
+    proposition: Proposition,
+) -> Result<u64, DaoServiceError> {
+    let (caller, canister_id, dip_service) = SERVICE.with(|service| {
+        let service = service.borrow();
+        (
+            service.env.caller(),
+            service.env.canister_id(),
+            DIP721Service::from(service.dip_service_principal),
+        )
+    });
+    let holdings = dip_service.get_space_holdings(housing_unit).await?;
+
+    //an accepted sale is executed by this canister, so the seller must have approved it on the
+    //token being sold before the holders vote on the sale
+    if let Proposition::UnitsSale(sale) = &proposition {
+        let approved = dip_service.is_approved_for_all(caller, canister_id).await?
+            || dip_service.operator_of(sale.token_id).await? == Some(canister_id);
+        if !approved {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(String::from(
+                    "approve propspace_backend as an operator of the token before offering its units",
+                )),
+            });
+        }
+    }
+
+    SERVICE.with(|service| {
+        service
+            .borrow_mut()
+            .submit_proposal(housing_unit, proposition, &holdings)
+    })
+}
+
+//a vote that accepts the proposal executes it straight away
+pub async fn vote(proposal_id: u64, ballot: Vote) -> Result<ProposalState, DaoServiceError> {
+    let (housing_unit, dip_service) = SERVICE.with(|service| {
+        let service = service.borrow();
+        let proposal = service.get_proposal(proposal_id)?;
+        Ok::<_, DaoServiceError>((
+            proposal.housing_unit,
+            DIP721Service::from(service.dip_service_principal),
+        ))
+    })?;
+    let holdings = dip_service.get_space_holdings(housing_unit).await?;
+
+    let state =
+        SERVICE.with(|service| service.borrow_mut().vote(proposal_id, ballot, &holdings))?;
+    if state == ProposalState::Accepted {
+        return execution::execute_proposal(proposal_id).await;
+    }
+    Ok(state)
+}
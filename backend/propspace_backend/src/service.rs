@@ -1,19 +1,26 @@
-use crate::dip721::NftResult;
 // use crate::dip721::DIPService;
+use crate::dip721::SpaceHolding;
 use crate::env::{EmptyEnvironment, Environment};
 use crate::types::*;
 use ic_cdk::export::Principal;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
 
+const MAX_PAGE_SIZE: u64 = 100;
+
 pub struct HousingDaoService {
     pub env: Box<dyn Environment>,
     pub dip_service_principal: Principal,
+    pub payments_service_principal: Principal,
     pub accounts: HashMap<Principal, Account>,
     pub proposals: HashMap<u64, Proposal>,
     pub spaces: HashMap<u64, Space>,
-    pub secret_key: String,
-    pub next_space_id: u64,
+    pub roles: HashMap<Principal, HashSet<Role>>,
+    pub next_proposal_id: u64,
+    pub proposal_config: ProposalConfig,
+    pub payouts: Vec<Payout>,
+    pub next_distribution_id: u64,
+    pub owed_refunds: Vec<OwedRefund>,
 }
 
 impl Default for HousingDaoService {
@@ -21,11 +28,16 @@ impl Default for HousingDaoService {
         HousingDaoService {
             env: Box::new(EmptyEnvironment {}),
             dip_service_principal: Principal::anonymous(),
+            payments_service_principal: Principal::anonymous(),
             accounts: HashMap::new(),
             proposals: HashMap::new(),
             spaces: HashMap::new(),
-            secret_key: String::from("default_key"),
-            next_space_id: 0,
+            roles: HashMap::new(),
+            next_proposal_id: 0,
+            proposal_config: ProposalConfig::default(),
+            payouts: vec![],
+            next_distribution_id: 0,
+            owed_refunds: vec![],
         }
     }
 }
@@ -46,7 +58,21 @@ impl From<HousingDaoStorage> for HousingDaoService {
             .map(|a| (a.id, a))
             .collect();
 
-        let spaces = storage
+        let next_proposal_id = storage
+            .proposals
+            .iter()
+            .map(|p| p.id + 1)
+            .max()
+            .unwrap_or_default();
+
+        let next_distribution_id = storage
+            .payouts
+            .iter()
+            .map(|p| p.distribution_id + 1)
+            .max()
+            .unwrap_or_default();
+
+        let spaces: HashMap<u64, Space> = storage
             .spaces
             .clone()
             .into_iter()
@@ -56,40 +82,245 @@ impl From<HousingDaoStorage> for HousingDaoService {
         HousingDaoService {
             env: Box::new(EmptyEnvironment {}),
             dip_service_principal: storage.dip_service_principal,
+            payments_service_principal: storage.payments_service_principal,
             accounts: accounts,
             proposals: proposals,
             spaces: spaces,
-            secret_key: storage.secret_key,
-            next_space_id: 0,
+            roles: storage
+                .roles
+                .into_iter()
+                .map(|(principal, roles)| (principal, roles.into_iter().collect()))
+                .collect(),
+            next_proposal_id,
+            proposal_config: storage.proposal_config,
+            payouts: storage.payouts,
+            next_distribution_id,
+            owed_refunds: storage.owed_refunds,
+        }
+    }
+}
+
+impl From<&HousingDaoService> for HousingDaoStorage {
+    fn from(service: &HousingDaoService) -> Self {
+        let mut accounts: Vec<Account> = service.accounts.values().cloned().collect();
+        accounts.sort_by_key(|a| a.principal);
+
+        let mut spaces = service.get_all_spaces();
+        spaces.sort_by_key(|s| s.id);
+
+        let mut roles: Vec<(Principal, Vec<Role>)> = service
+            .roles
+            .iter()
+            .map(|(principal, roles)| {
+                let mut roles: Vec<Role> = roles.iter().copied().collect();
+                roles.sort();
+                (*principal, roles)
+            })
+            .collect();
+        roles.sort_by_key(|(principal, _)| *principal);
+
+        HousingDaoStorage {
+            dip_service_principal: service.dip_service_principal,
+            payments_service_principal: service.payments_service_principal,
+            accounts,
+            proposals: service.list_proposals(),
+            spaces,
+            roles,
+            proposal_config: service.proposal_config,
+            payouts: service.payouts.clone(),
+            owed_refunds: service.owed_refunds.clone(),
         }
     }
 }
 
 impl HousingDaoService {
-    pub async fn random_call(&self, token_id: u64) {
-        let tokenRes: Result<(NftResult,), _> =
-            ic_cdk::call(self.dip_service_principal, "burnToken", (token_id,)).await;
+    //registers the caller as an investor with no holdings
+    pub fn create_account(&mut self) -> Result<Principal, DaoServiceError> {
+        let caller = self.env.caller();
+        if caller == Principal::anonymous() {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(String::from(
+                    "anonymous callers cannot register",
+                )),
+            });
+        }
+        if self.accounts.contains_key(&caller) {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("account already exists")),
+            });
+        }
+
+        self.accounts.insert(
+            caller,
+            Account {
+                principal: caller,
+                housing_units: vec![],
+                kyc: KycStatus::Unverified,
+            },
+        );
+        self.roles.entry(caller).or_default().insert(Role::Investor);
+        Ok(caller)
     }
 
-    pub fn create_account(
-        &mut self,
-        secret_key: String,
-        account: Account,
-    ) -> Result<Principal, DaoServiceError> {
-        if secret_key == self.secret_key {
-            self.accounts.insert(account.principal, account.clone());
-            return Ok(account.principal);
+    pub fn has_role(&self, principal: &Principal, role: Role) -> bool {
+        self.roles
+            .get(principal)
+            .is_some_and(|roles| roles.contains(&role) || roles.contains(&Role::Admin))
+    }
+
+    pub fn require_role(&self, role: Role) -> Result<(), DaoServiceError> {
+        if self.has_role(&self.env.caller(), role) {
+            Ok(())
         } else {
+            Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(format!("caller is not a {:?}", role)),
+            })
+        }
+    }
+
+    pub fn get_roles(&self, principal: &Principal) -> Vec<Role> {
+        let mut roles: Vec<Role> = self
+            .roles
+            .get(principal)
+            .map(|roles| roles.iter().copied().collect())
+            .unwrap_or_default();
+        roles.sort();
+        roles
+    }
+
+    pub fn grant_role(&mut self, principal: Principal, role: Role) -> Result<(), DaoServiceError> {
+        self.require_role(Role::Admin)?;
+        self.roles.entry(principal).or_default().insert(role);
+        Ok(())
+    }
+
+    //the last admin can't be revoked so the canister is never left without one
+    pub fn revoke_role(&mut self, principal: Principal, role: Role) -> Result<(), DaoServiceError> {
+        self.require_role(Role::Admin)?;
+        if role == Role::Admin
+            && self
+                .roles
+                .iter()
+                .filter(|(p, roles)| **p != principal && roles.contains(&Role::Admin))
+                .count()
+                == 0
+        {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("cannot revoke the last admin")),
+            });
+        }
+
+        if let Some(roles) = self.roles.get_mut(&principal) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                self.roles.remove(&principal);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_verified(&self, principal: &Principal) -> bool {
+        match self.accounts.get(principal).map(|a| &a.kyc) {
+            Some(KycStatus::Verified { expires_at, .. }) => *expires_at > self.env.now(),
+            _ => false,
+        }
+    }
+
+    pub fn require_verified(&self, principal: &Principal) -> Result<(), DaoServiceError> {
+        if self.is_verified(principal) {
+            Ok(())
+        } else {
+            Err(DaoServiceError {
+                error_type: ErrorType::NotVerified(format!("{} is not verified", principal)),
+            })
+        }
+    }
+
+    //checks the update without applying it, returns the time until which the holder may trade
+    //for the dip721 canister
+    pub fn check_kyc_status(
+        &self,
+        principal: &Principal,
+        kyc: &KycStatus,
+    ) -> Result<Option<u64>, DaoServiceError> {
+        self.require_role(Role::Auditor)?;
+        if !self.accounts.contains_key(principal) {
             return Err(DaoServiceError {
-                error_type: ErrorType::Failure(String::from("unable to create space")),
+                error_type: ErrorType::NotFound(String::from("account not found")),
             });
         }
+
+        Ok(match kyc {
+            KycStatus::Verified { expires_at, .. } => Some(*expires_at),
+            _ => None,
+        })
+    }
+
+    //applied once the dip721 canister has accepted the matching trading window
+    pub fn apply_kyc_status(&mut self, principal: &Principal, kyc: KycStatus) {
+        if let Some(account) = self.accounts.get_mut(principal) {
+            account.kyc = kyc;
+        }
     }
 
     pub fn get_all_spaces(&self) -> Vec<Space> {
         self.spaces.values().cloned().collect()
     }
 
+    pub fn search_units(
+        &self,
+        filter: SpaceFilter,
+        sort: SpaceSort,
+        offset: u64,
+        limit: u64,
+    ) -> SpacePage {
+        let location = filter.location.map(|l| l.to_lowercase());
+        let mut spaces: Vec<Space> = self
+            .spaces
+            .values()
+            .filter(|space| {
+                let details = &space.details;
+                location
+                    .as_ref()
+                    .is_none_or(|l| details.location.to_lowercase().contains(l))
+                    && filter
+                        .min_price_per_unit
+                        .is_none_or(|p| details.price_per_unit >= p)
+                    && filter
+                        .max_price_per_unit
+                        .is_none_or(|p| details.price_per_unit <= p)
+                    && filter
+                        .min_units_available
+                        .is_none_or(|u| details.units_available >= u)
+                    && filter.owner.is_none_or(|o| details.owner == o)
+            })
+            .cloned()
+            .collect();
+
+        //ties are broken by id so pages are stable between calls
+        match sort {
+            SpaceSort::IdAsc => spaces.sort_by_key(|s| s.id),
+            SpaceSort::PriceAsc => spaces.sort_by_key(|s| (s.details.price_per_unit, s.id)),
+            SpaceSort::PriceDesc => {
+                spaces.sort_by_key(|s| (std::cmp::Reverse(s.details.price_per_unit), s.id))
+            }
+            SpaceSort::UnitsAvailableDesc => {
+                spaces.sort_by_key(|s| (std::cmp::Reverse(s.details.units_available), s.id))
+            }
+        }
+
+        let total = spaces.len() as u64;
+        let limit = limit.min(MAX_PAGE_SIZE);
+        SpacePage {
+            spaces: spaces
+                .into_iter()
+                .skip(offset as usize)
+                .take(limit as usize)
+                .collect(),
+            total,
+        }
+    }
+
     pub fn get_account_details(&self) -> Result<Account, DaoServiceError> {
         let caller = self.env.caller();
         match self.accounts.get(&caller) {
@@ -102,42 +333,45 @@ impl HousingDaoService {
         };
     }
 
-    pub fn create_space(
+    //checked before the dip721 canister assigns the id, returns the principal that will own the space
+    pub fn authorize_space_creation(&self) -> Result<Principal, DaoServiceError> {
+        self.require_role(Role::PropertyManager)?;
+        Ok(self.env.caller())
+    }
+
+    //registers a space under the id assigned to it by the dip721 canister, the owner was
+    //authorized before that call so a role revoked meanwhile does not orphan the dip721 space
+    pub fn register_space(
         &mut self,
+        owner: Principal,
+        space_id: u64,
         mut space_details: SpaceDetails,
     ) -> Result<u64, DaoServiceError> {
-        let caller = self.env.caller();
-        space_details.owner = caller;
+        if self.spaces.contains_key(&space_id) {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("space already exists")),
+            });
+        }
+
+        space_details.owner = owner;
         let space = Space {
-            id: self.next_space_id,
+            id: space_id,
             details: space_details,
         };
-        self.next_space_id += 1;
 
-        match self.spaces.insert(space.id, space) {
-            Some(space) => return Ok(space.id),
-            None => {
-                return Err(DaoServiceError {
-                    error_type: ErrorType::Failure(String::from("unable to add space")),
-                })
-            }
-        }
+        self.spaces.insert(space.id, space);
+        Ok(space_id)
     }
 
-    pub fn list_accounts(&self) -> Vec<Account> {
-        self.accounts.values().cloned().collect()
+    pub fn export_storage(&self) -> Result<HousingDaoStorage, DaoServiceError> {
+        self.require_role(Role::Auditor)?;
+        Ok(HousingDaoStorage::from(self))
     }
 
-    pub fn get_balance(&self) -> Result<Tokens, DaoServiceError> {
-        let caller = self.env.caller();
-        match self.accounts.get(&caller) {
-            Some(account) => return Ok(account.tokens),
-            None => {
-                return Err(DaoServiceError {
-                    error_type: ErrorType::NotFound(String::from("account not found")),
-                })
-            }
-        }
+    //accounts carry the kyc status of every investor, so only auditors list them
+    pub fn list_accounts(&self) -> Result<Vec<Account>, DaoServiceError> {
+        self.require_role(Role::Auditor)?;
+        Ok(self.accounts.values().cloned().collect())
     }
 
     pub fn get_housing_units(&self) -> Result<Vec<HousingUnit>, DaoServiceError> {
@@ -171,4 +405,1247 @@ impl HousingDaoService {
             }
         }
     }
+
+    //voting power of a principal is the number of units its dip721 tokens hold in the space,
+    //read from the dip721 canister so units moved there directly carry their votes with them
+    fn voting_power(principal: &Principal, holdings: &[SpaceHolding]) -> u64 {
+        holdings
+            .iter()
+            .filter(|holding| holding.owner == *principal)
+            .map(|holding| holding.num_units)
+            .sum()
+    }
+
+    fn total_voting_power(holdings: &[SpaceHolding]) -> u64 {
+        holdings.iter().map(|holding| holding.num_units).sum()
+    }
+
+    //holdings are the live dip721 tokens of the space the proposal is for
+    pub fn submit_proposal(
+        &mut self,
+        housing_unit: u64,
+        proposition: Proposition,
+        holdings: &[SpaceHolding],
+    ) -> Result<u64, DaoServiceError> {
+        let caller = self.env.caller();
+        if !self.spaces.contains_key(&housing_unit) {
+            return Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("space not found")),
+            });
+        }
+
+        if Self::voting_power(&caller, holdings) == 0 {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(String::from(
+                    "only unit holders can submit proposals for a space",
+                )),
+            });
+        }
+
+        if let Proposition::UnitsSale(sale) = &proposition {
+            let held = holdings
+                .iter()
+                .find(|holding| holding.token_id == sale.token_id && holding.owner == caller)
+                .map(|holding| holding.num_units)
+                .unwrap_or(0);
+            if sale.num_units == 0 || sale.num_units > held {
+                return Err(DaoServiceError {
+                    error_type: ErrorType::Failure(String::from(
+                        "proposer does not hold the units offered for sale",
+                    )),
+                });
+            }
+        }
+
+        let now = self.env.now();
+        let proposal = Proposal {
+            id: self.next_proposal_id,
+            timestamp: now,
+            housing_unit,
+            proposer: caller,
+            proposition,
+            state: ProposalState::Open,
+            percentage_for: 0.0,
+            percentage_against: 0.0,
+            percentage_abstain: 0.0,
+            voters: vec![],
+            token_weights: holdings
+                .iter()
+                .map(|holding| TokenWeight {
+                    token_id: holding.token_id,
+                    num_units: holding.num_units,
+                })
+                .collect(),
+            total_units: Self::total_voting_power(holdings),
+            voted_tokens: vec![],
+            deadline: now + self.proposal_config.voting_period,
+            quorum: self.proposal_config.quorum,
+            threshold: self.proposal_config.threshold,
+        };
+        self.next_proposal_id += 1;
+
+        self.proposals.insert(proposal.id, proposal.clone());
+        Ok(proposal.id)
+    }
+
+    pub fn vote(
+        &mut self,
+        proposal_id: u64,
+        ballot: Vote,
+        holdings: &[SpaceHolding],
+    ) -> Result<ProposalState, DaoServiceError> {
+        let caller = self.env.caller();
+        if !self.proposals.contains_key(&proposal_id) {
+            return Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("proposal not found")),
+            });
+        }
+
+        self.require_verified(&caller)?;
+        //tokens are voted with by whoever holds them now
+        let held_tokens: Vec<u64> = holdings
+            .iter()
+            .filter(|holding| holding.owner == caller)
+            .map(|holding| holding.token_id)
+            .collect();
+        if held_tokens.is_empty() {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(String::from(
+                    "caller holds no units in this space",
+                )),
+            });
+        }
+
+        let now = self.env.now();
+        let proposal = self.proposals.get_mut(&proposal_id).unwrap();
+        if proposal.state != ProposalState::Open || now > proposal.deadline {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("proposal is not open for voting")),
+            });
+        }
+
+        //each token votes once, with the units it held when the proposal was submitted
+        let (token_ids, units): (Vec<u64>, Vec<u64>) = proposal
+            .token_weights
+            .iter()
+            .filter(|weight| {
+                held_tokens.contains(&weight.token_id)
+                    && !proposal.voted_tokens.contains(&weight.token_id)
+            })
+            .map(|weight| (weight.token_id, weight.num_units))
+            .unzip();
+        if token_ids.is_empty() {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from(
+                    "the caller's tokens have already voted or were minted after the proposal",
+                )),
+            });
+        }
+
+        let weight = units.iter().sum::<u64>() as f64 / proposal.total_units as f64 * 100.0;
+        match ballot {
+            Vote::Yes => proposal.percentage_for += weight,
+            Vote::No => proposal.percentage_against += weight,
+            Vote::Abstain => proposal.percentage_abstain += weight,
+        }
+        proposal.voted_tokens.extend(token_ids);
+        if !proposal.voters.contains(&caller) {
+            proposal.voters.push(caller);
+        }
+
+        if let Some(state) = outcome(proposal, false) {
+            proposal.state = state;
+        }
+
+        Ok(proposal.state.clone())
+    }
+
+    //closes open proposals past their deadline and returns the ids of those accepted
+    pub fn close_expired_proposals(&mut self) -> Vec<u64> {
+        let now = self.env.now();
+        let mut accepted = vec![];
+        for proposal in self.proposals.values_mut() {
+            if proposal.state != ProposalState::Open || now <= proposal.deadline {
+                continue;
+            }
+
+            if let Some(state) = outcome(proposal, true) {
+                if state == ProposalState::Accepted {
+                    accepted.push(proposal.id);
+                }
+                proposal.state = state;
+            }
+        }
+        accepted.sort();
+        accepted
+    }
+
+    //marks an accepted proposal as executing and returns it so its proposition can be carried out
+    pub fn begin_execution(&mut self, proposal_id: u64) -> Result<Proposal, DaoServiceError> {
+        match self.proposals.get_mut(&proposal_id) {
+            Some(proposal) if proposal.state == ProposalState::Accepted => {
+                proposal.state = ProposalState::Executing;
+                Ok(proposal.clone())
+            }
+            Some(_) => Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("proposal has not been accepted")),
+            }),
+            None => Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("proposal not found")),
+            }),
+        }
+    }
+
+    //records the outcome of an executing proposal and applies its effects on the dao state
+    pub fn complete_execution(
+        &mut self,
+        proposal_id: u64,
+        result: Result<(), DaoServiceError>,
+    ) -> ProposalState {
+        let proposal = match self.proposals.get(&proposal_id) {
+            Some(proposal) => proposal.clone(),
+            None => return ProposalState::Failed(String::from("proposal not found")),
+        };
+
+        let state = match result {
+            Ok(()) => {
+                match &proposal.proposition {
+                    Proposition::UnitsSale(sale) => self.move_units(
+                        proposal.proposer,
+                        sale.buyer_account,
+                        proposal.housing_unit,
+                        sale.num_units,
+                    ),
+                    Proposition::SetPrice(price) => {
+                        if let Some(space) = self.spaces.get_mut(&proposal.housing_unit) {
+                            space.details.price_per_unit = price.new_price;
+                        }
+                    }
+                    Proposition::Other(_) => {}
+                }
+                ProposalState::Succeeded
+            }
+            Err(err) => ProposalState::Failed(format!("{:?}", err.error_type)),
+        };
+
+        if let Some(proposal) = self.proposals.get_mut(&proposal_id) {
+            proposal.state = state.clone();
+        }
+        state
+    }
+
+    fn move_units(
+        &mut self,
+        sender: Principal,
+        receiver: Principal,
+        space_id: u64,
+        num_units: u64,
+    ) {
+        if let Some(account) = self.accounts.get_mut(&sender) {
+            let mut remaining = num_units;
+            for unit in account
+                .housing_units
+                .iter_mut()
+                .filter(|x| x.id == space_id)
+            {
+                let debit = remaining.min(unit.num_units);
+                unit.num_units -= debit;
+                remaining -= debit;
+            }
+            account.housing_units.retain(|x| x.num_units > 0);
+        }
+
+        self.credit_units(receiver, space_id, num_units);
+    }
+
+    fn credit_units(&mut self, receiver: Principal, space_id: u64, num_units: u64) {
+        if let Some(account) = self.accounts.get_mut(&receiver) {
+            match account.housing_units.iter_mut().find(|x| x.id == space_id) {
+                Some(unit) => unit.num_units += num_units,
+                None => account.housing_units.push(HousingUnit {
+                    id: space_id,
+                    num_units,
+                }),
+            }
+        }
+    }
+
+    //holds the units back from the space until the purchase is completed or cancelled,
+    //the cost is collected from the caller's ledger account before the token is minted
+    pub fn reserve_purchase(
+        &mut self,
+        space_id: u64,
+        num_units: u64,
+    ) -> Result<PendingPurchase, DaoServiceError> {
+        self.require_role(Role::Investor)?;
+        let caller = self.env.caller();
+        self.require_verified(&caller)?;
+        let space = match self.spaces.get(&space_id) {
+            Some(space) => space.clone(),
+            None => {
+                return Err(DaoServiceError {
+                    error_type: ErrorType::NotFound(String::from("space not found")),
+                })
+            }
+        };
+
+        if num_units == 0 || num_units > space.details.units_available {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("units not available")),
+            });
+        }
+
+        let cost = match space.details.price_per_unit.checked_mul(num_units) {
+            Some(cost) => cost,
+            None => {
+                return Err(DaoServiceError {
+                    error_type: ErrorType::Failure(String::from("purchase amount overflows")),
+                })
+            }
+        };
+
+        if !self.accounts.contains_key(&caller) {
+            return Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("account not found")),
+            });
+        }
+
+        if let Some(space) = self.spaces.get_mut(&space_id) {
+            space.details.units_available -= num_units;
+        }
+
+        Ok(PendingPurchase {
+            buyer: caller,
+            space_id,
+            num_units,
+            cost,
+            details: space.details,
+        })
+    }
+
+    pub fn complete_purchase(&mut self, purchase: &PendingPurchase) {
+        self.credit_units(purchase.buyer, purchase.space_id, purchase.num_units);
+    }
+
+    //rolls back a reservation whose mint or payment failed
+    pub fn cancel_purchase(&mut self, purchase: &PendingPurchase) {
+        if let Some(space) = self.spaces.get_mut(&purchase.space_id) {
+            space.details.units_available += purchase.num_units;
+        }
+    }
+
+    pub fn record_owed_refund(&mut self, purchase: &PendingPurchase, reason: String) {
+        self.owed_refunds.push(OwedRefund {
+            buyer: purchase.buyer,
+            space_id: purchase.space_id,
+            num_units: purchase.num_units,
+            amount_e8s: purchase.cost,
+            timestamp: self.env.now(),
+            reason,
+        });
+    }
+
+    pub fn get_owed_refunds(&self) -> Result<Vec<OwedRefund>, DaoServiceError> {
+        self.require_role(Role::Auditor)?;
+        Ok(self.owed_refunds.clone())
+    }
+
+    //hands the owed refunds over to be paid, those that fail again are recorded anew
+    pub fn take_owed_refunds(&mut self) -> Result<Vec<OwedRefund>, DaoServiceError> {
+        self.require_role(Role::Admin)?;
+        Ok(std::mem::take(&mut self.owed_refunds))
+    }
+
+    //the space owner or a property manager may pay out the income of a space
+    fn require_space_manager(&self, space_id: u64) -> Result<(), DaoServiceError> {
+        let caller = self.env.caller();
+        match self.spaces.get(&space_id) {
+            Some(space)
+                if space.details.owner == caller
+                    || self.has_role(&caller, Role::PropertyManager) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(DaoServiceError {
+                error_type: ErrorType::Unauthorized(String::from(
+                    "only the space owner or a property manager can distribute its income",
+                )),
+            }),
+            None => Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("space not found")),
+            }),
+        }
+    }
+
+    //returns the id of the new distribution
+    pub fn begin_distribution(
+        &mut self,
+        space_id: u64,
+        amount_e8s: u64,
+    ) -> Result<u64, DaoServiceError> {
+        self.require_space_manager(space_id)?;
+
+        if amount_e8s == 0 {
+            return Err(DaoServiceError {
+                error_type: ErrorType::Failure(String::from("nothing to distribute")),
+            });
+        }
+
+        let distribution_id = self.next_distribution_id;
+        self.next_distribution_id += 1;
+        Ok(distribution_id)
+    }
+
+    pub fn record_payouts(&mut self, payouts: &[Payout]) {
+        self.payouts.extend_from_slice(payouts);
+    }
+
+    //a distribution pays each token at most once, so a payout is keyed by both ids
+    pub fn settle_payout(&mut self, distribution_id: u64, token_id: u64, status: PayoutStatus) {
+        if let Some(payout) = self
+            .payouts
+            .iter_mut()
+            .find(|p| p.distribution_id == distribution_id && p.token_id == token_id)
+        {
+            payout.timestamp = self.env.now();
+            payout.status = status;
+        }
+    }
+
+    //marks the failed payouts of a distribution pending again and returns them to be retried
+    pub fn take_failed_payouts(
+        &mut self,
+        distribution_id: u64,
+    ) -> Result<Vec<Payout>, DaoServiceError> {
+        let space_id = match self
+            .payouts
+            .iter()
+            .find(|p| p.distribution_id == distribution_id)
+        {
+            Some(payout) => payout.space_id,
+            None => {
+                return Err(DaoServiceError {
+                    error_type: ErrorType::NotFound(String::from("distribution not found")),
+                })
+            }
+        };
+        self.require_space_manager(space_id)?;
+
+        let mut failed = vec![];
+        for payout in self
+            .payouts
+            .iter_mut()
+            .filter(|p| p.distribution_id == distribution_id)
+        {
+            if let PayoutStatus::Failed(_) = payout.status {
+                payout.status = PayoutStatus::Pending;
+                failed.push(payout.clone());
+            }
+        }
+        Ok(failed)
+    }
+
+    //spaces in id order, payouts in the order they were made
+    pub fn income_statement(&self, owner: Principal) -> IncomeStatement {
+        let mut spaces: BTreeMap<u64, SpaceIncome> = BTreeMap::new();
+        for payout in self.payouts.iter().filter(|p| p.owner == owner) {
+            let space = spaces.entry(payout.space_id).or_insert(SpaceIncome {
+                space_id: payout.space_id,
+                lines: vec![],
+                total_e8s: 0,
+            });
+            if let PayoutStatus::Paid { .. } = payout.status {
+                space.total_e8s += payout.amount_e8s;
+            }
+            space.lines.push(IncomeStatementLine {
+                distribution_id: payout.distribution_id,
+                token_id: payout.token_id,
+                timestamp: payout.timestamp,
+                num_units: payout.num_units,
+                amount_e8s: payout.amount_e8s,
+                status: payout.status.clone(),
+                cumulative_e8s: space.total_e8s,
+            });
+        }
+
+        let spaces: Vec<SpaceIncome> = spaces.into_values().collect();
+        IncomeStatement {
+            owner,
+            total_e8s: spaces.iter().map(|s| s.total_e8s).sum(),
+            spaces,
+        }
+    }
+
+    //one row per payout, timestamps are nanoseconds since the epoch
+    pub fn income_statement_csv(&self, owner: Principal) -> String {
+        let mut csv = String::from(
+            "space_id,distribution_id,token_id,timestamp,num_units,amount_e8s,status,block_index,cumulative_e8s\n",
+        );
+        for space in self.income_statement(owner).spaces {
+            for line in space.lines {
+                let (status, block_index) = match line.status {
+                    PayoutStatus::Paid { block_index } => ("paid", block_index.to_string()),
+                    PayoutStatus::Pending => ("pending", String::new()),
+                    PayoutStatus::Failed(_) => ("failed", String::new()),
+                };
+                csv.push_str(&format!(
+                    "{},{},{},{},{},{},{},{},{}\n",
+                    space.space_id,
+                    line.distribution_id,
+                    line.token_id,
+                    line.timestamp,
+                    line.num_units,
+                    line.amount_e8s,
+                    status,
+                    block_index,
+                    line.cumulative_e8s
+                ));
+            }
+        }
+        csv
+    }
+
+    pub fn get_proposal(&self, proposal_id: u64) -> Result<Proposal, DaoServiceError> {
+        match self.proposals.get(&proposal_id) {
+            Some(proposal) => Ok(proposal.clone()),
+            None => Err(DaoServiceError {
+                error_type: ErrorType::NotFound(String::from("proposal not found")),
+            }),
+        }
+    }
+
+    pub fn list_proposals(&self) -> Vec<Proposal> {
+        let mut proposals: Vec<Proposal> = self.proposals.values().cloned().collect();
+        proposals.sort_by_key(|p| p.id);
+        proposals
+    }
+}
+
+//splits an amount over the holdings in proportion to their units
+//the e8s left over by rounding down go one each to the largest remainders, ties to the earlier holding
+pub fn split_income(amount_e8s: u64, holdings: &[SpaceHolding]) -> Option<Vec<u64>> {
+    let total_units: u128 = holdings.iter().map(|h| h.num_units as u128).sum();
+    if total_units == 0 {
+        return None;
+    }
+
+    let mut shares = Vec::with_capacity(holdings.len());
+    let mut remainders = Vec::with_capacity(holdings.len());
+    for (index, holding) in holdings.iter().enumerate() {
+        let weighted = amount_e8s as u128 * holding.num_units as u128;
+        shares.push((weighted / total_units) as u64);
+        remainders.push((weighted % total_units, index));
+    }
+
+    let dust = amount_e8s - shares.iter().sum::<u64>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, index) in remainders.into_iter().take(dust as usize) {
+        shares[index] += 1;
+    }
+    Some(shares)
+}
+
+//computes the outcome of an open proposal from its stored percentages
+//before closing, a proposal is only settled once the votes still outstanding can't change the result
+fn outcome(proposal: &Proposal, closing: bool) -> Option<ProposalState> {
+    let turnout =
+        proposal.percentage_for + proposal.percentage_against + proposal.percentage_abstain;
+    let outstanding = if closing {
+        0.0
+    } else {
+        (100.0 - turnout).max(0.0)
+    };
+
+    let worst_case = proposal.percentage_for + proposal.percentage_against + outstanding;
+    let best_case = proposal.percentage_for + outstanding;
+
+    if turnout >= proposal.quorum
+        && worst_case > 0.0
+        && proposal.percentage_for / worst_case * 100.0 > proposal.threshold
+    {
+        return Some(ProposalState::Accepted);
+    }
+
+    if closing || best_case == 0.0 || best_case / worst_case * 100.0 <= proposal.threshold {
+        return Some(ProposalState::Rejected);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::TestEnvironment;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn service_with_holders(holders: &[(Principal, u64)]) -> HousingDaoService {
+        let mut service = HousingDaoService::default();
+        service.spaces.insert(
+            0,
+            Space {
+                id: 0,
+                details: SpaceDetails {
+                    owner: principal(0),
+                    location: String::from("Lagos"),
+                    description: String::new(),
+                    price_per_unit: 100,
+                    units_available: 10,
+                },
+            },
+        );
+        for (holder, num_units) in holders {
+            service.accounts.insert(
+                *holder,
+                Account {
+                    principal: *holder,
+                    housing_units: vec![HousingUnit {
+                        id: 0,
+                        num_units: *num_units,
+                    }],
+                    kyc: KycStatus::Verified {
+                        jurisdiction: String::from("NG"),
+                        expires_at: u64::MAX,
+                    },
+                },
+            );
+            service
+                .roles
+                .insert(*holder, HashSet::from([Role::Investor]));
+        }
+        service
+    }
+
+    //the dip721 tokens matching the units recorded for each account, one token per holder
+    //numbered after the holder
+    fn holdings(service: &HousingDaoService) -> Vec<SpaceHolding> {
+        let mut holdings: Vec<SpaceHolding> = service
+            .accounts
+            .values()
+            .flat_map(|account| {
+                account
+                    .housing_units
+                    .iter()
+                    .filter(|unit| unit.id == 0)
+                    .map(|unit| SpaceHolding {
+                        token_id: account.principal.as_slice()[0] as u64,
+                        owner: account.principal,
+                        num_units: unit.num_units,
+                    })
+            })
+            .collect();
+        holdings.sort_by_key(|holding| holding.token_id);
+        holdings
+    }
+
+    fn set_caller(service: &mut HousingDaoService, caller: Principal) {
+        set_env(service, caller, 0);
+    }
+
+    fn set_env(service: &mut HousingDaoService, caller: Principal, now: u64) {
+        service.env = Box::new(TestEnvironment {
+            now,
+            caller,
+            canister_id: Principal::anonymous(),
+        });
+    }
+
+    #[test]
+    fn votes_are_weighted_by_units_held() {
+        let mut service =
+            service_with_holders(&[(principal(1), 2), (principal(2), 1), (principal(3), 1)]);
+
+        set_caller(&mut service, principal(1));
+        let proposal_id = service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("repaint the building")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+        assert!(service
+            .vote(proposal_id, Vote::Yes, &holdings(&service))
+            .is_ok());
+
+        set_caller(&mut service, principal(2));
+        assert!(service
+            .vote(proposal_id, Vote::No, &holdings(&service))
+            .is_ok());
+        assert!(service
+            .vote(proposal_id, Vote::No, &holdings(&service))
+            .is_err());
+
+        let proposal = service.get_proposal(proposal_id).ok().unwrap();
+        assert_eq!(proposal.percentage_for, 50.0);
+        assert_eq!(proposal.percentage_against, 25.0);
+        assert_eq!(proposal.voters, vec![principal(1), principal(2)]);
+        assert_eq!(proposal.state, ProposalState::Open);
+    }
+
+    #[test]
+    fn executed_unit_sale_moves_holdings() {
+        let mut service = service_with_holders(&[(principal(1), 3), (principal(2), 1)]);
+
+        set_caller(&mut service, principal(1));
+        let sale = UnitSaleProposition {
+            token_id: 1,
+            num_units: 2,
+            buyer_account: principal(2),
+        };
+        let proposal_id = service
+            .submit_proposal(0, Proposition::UnitsSale(sale), &holdings(&service))
+            .ok()
+            .unwrap();
+        assert_eq!(
+            service
+                .vote(proposal_id, Vote::Yes, &holdings(&service))
+                .ok(),
+            Some(ProposalState::Accepted)
+        );
+
+        assert!(service.begin_execution(proposal_id).is_ok());
+        assert!(service.begin_execution(proposal_id).is_err());
+        assert_eq!(
+            service.complete_execution(proposal_id, Ok(())),
+            ProposalState::Succeeded
+        );
+
+        assert_eq!(
+            HousingDaoService::voting_power(&principal(1), &holdings(&service)),
+            1
+        );
+        assert_eq!(
+            HousingDaoService::voting_power(&principal(2), &holdings(&service)),
+            3
+        );
+    }
+
+    #[test]
+    fn votes_follow_tokens_moved_on_dip721() {
+        let mut service = service_with_holders(&[(principal(1), 3), (principal(2), 1)]);
+        //principal 1 transferred its token on the dip721 canister without the backend knowing
+        let mut moved = holdings(&service);
+        moved[0].owner = principal(2);
+
+        set_caller(&mut service, principal(1));
+        let sale = UnitSaleProposition {
+            token_id: 1,
+            num_units: 1,
+            buyer_account: principal(2),
+        };
+        assert!(service
+            .submit_proposal(0, Proposition::UnitsSale(sale.clone()), &moved)
+            .is_err());
+        assert!(service
+            .submit_proposal(0, Proposition::Other(String::from("paint")), &moved)
+            .is_err());
+
+        set_caller(&mut service, principal(2));
+        //the units sold must come from the token named in the sale
+        let sale = UnitSaleProposition {
+            num_units: 4,
+            ..sale
+        };
+        assert!(service
+            .submit_proposal(0, Proposition::UnitsSale(sale), &moved)
+            .is_err());
+        let proposal_id = service
+            .submit_proposal(0, Proposition::Other(String::from("paint")), &moved)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            service.vote(proposal_id, Vote::Yes, &moved).ok(),
+            Some(ProposalState::Accepted)
+        );
+    }
+
+    #[test]
+    fn tokens_moved_between_votes_are_counted_once() {
+        let mut service =
+            service_with_holders(&[(principal(1), 2), (principal(2), 1), (principal(3), 2)]);
+
+        set_caller(&mut service, principal(1));
+        let proposal_id = service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("paint")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+        assert!(service
+            .vote(proposal_id, Vote::No, &holdings(&service))
+            .is_ok());
+
+        //principal 1 then moved its token to principal 2 on the dip721 canister
+        let mut moved = holdings(&service);
+        moved[0].owner = principal(2);
+        //a token minted after the proposal was submitted carries no vote
+        moved.push(SpaceHolding {
+            token_id: 9,
+            owner: principal(1),
+            num_units: 4,
+        });
+        assert!(service.vote(proposal_id, Vote::Yes, &moved).is_err());
+
+        set_caller(&mut service, principal(2));
+        assert!(service.vote(proposal_id, Vote::No, &moved).is_ok());
+        assert!(service.vote(proposal_id, Vote::No, &moved).is_err());
+
+        let proposal = service.get_proposal(proposal_id).ok().unwrap();
+        assert_eq!(proposal.percentage_against, 60.0);
+        assert_eq!(proposal.percentage_for, 0.0);
+        assert_eq!(proposal.voted_tokens, vec![1, 2]);
+        assert_eq!(proposal.state, ProposalState::Rejected);
+    }
+
+    #[test]
+    fn non_holders_cannot_vote() {
+        let mut service = service_with_holders(&[(principal(1), 3)]);
+
+        set_caller(&mut service, principal(1));
+        let proposal_id = service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("repaint the building")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+
+        set_caller(&mut service, principal(3));
+        assert!(service
+            .vote(proposal_id, Vote::Yes, &holdings(&service))
+            .is_err());
+    }
+
+    #[test]
+    fn expired_proposals_are_closed_by_quorum_and_threshold() {
+        let mut service =
+            service_with_holders(&[(principal(1), 3), (principal(2), 2), (principal(3), 5)]);
+        let deadline = service.proposal_config.voting_period;
+
+        set_caller(&mut service, principal(1));
+        let short_of_quorum = service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("add a gym")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+        let accepted = service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("fix the roof")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+        assert!(service
+            .vote(short_of_quorum, Vote::Yes, &holdings(&service))
+            .is_ok());
+        assert!(service
+            .vote(accepted, Vote::Yes, &holdings(&service))
+            .is_ok());
+
+        set_caller(&mut service, principal(2));
+        assert!(service
+            .vote(accepted, Vote::Abstain, &holdings(&service))
+            .is_ok());
+        assert_eq!(
+            service.get_proposal(accepted).ok().unwrap().state,
+            ProposalState::Open
+        );
+
+        set_env(&mut service, principal(1), deadline);
+        assert!(service.close_expired_proposals().is_empty());
+
+        set_env(&mut service, principal(1), deadline + 1);
+        assert_eq!(service.close_expired_proposals(), vec![accepted]);
+        assert_eq!(
+            service.get_proposal(short_of_quorum).ok().unwrap().state,
+            ProposalState::Rejected
+        );
+        assert!(service
+            .vote(short_of_quorum, Vote::Yes, &holdings(&service))
+            .is_err());
+    }
+
+    #[test]
+    fn storage_round_trip_keeps_spaces_and_proposals() {
+        let mut service = service_with_holders(&[(principal(1), 3)]);
+        set_caller(&mut service, principal(1));
+        let details = service.spaces[&0].details.clone();
+        assert!(service.authorize_space_creation().is_err());
+        service
+            .roles
+            .insert(principal(1), HashSet::from([Role::PropertyManager]));
+        let owner = service.authorize_space_creation().ok().unwrap();
+        //the role is not checked again once the dip721 canister has created the space
+        service.roles.remove(&principal(1));
+        assert!(service.register_space(owner, 1, details.clone()).is_ok());
+        assert_eq!(service.spaces[&1].details.owner, principal(1));
+        assert!(service.register_space(owner, 1, details).is_err());
+        service
+            .roles
+            .insert(principal(1), HashSet::from([Role::PropertyManager]));
+        service
+            .submit_proposal(
+                0,
+                Proposition::Other(String::from("fix the roof")),
+                &holdings(&service),
+            )
+            .ok()
+            .unwrap();
+
+        service
+            .roles
+            .insert(principal(2), HashSet::from([Role::Auditor]));
+
+        let storage = HousingDaoStorage::from(&service);
+        let mut restored = HousingDaoService::from(storage.clone());
+        assert_eq!(restored.next_proposal_id, service.next_proposal_id);
+        assert_eq!(restored.list_proposals(), service.list_proposals());
+        assert_eq!(HousingDaoStorage::from(&restored).spaces, storage.spaces);
+        set_caller(&mut restored, principal(1));
+        assert!(restored.export_storage().is_err());
+        set_caller(&mut restored, principal(2));
+        assert_eq!(
+            restored.export_storage().ok().unwrap().roles,
+            vec![
+                (principal(1), vec![Role::PropertyManager]),
+                (principal(2), vec![Role::Auditor])
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_purchase_releases_the_units() {
+        let mut service = service_with_holders(&[(principal(1), 0)]);
+        set_caller(&mut service, principal(2));
+        assert!(service.reserve_purchase(0, 4).is_err());
+        set_caller(&mut service, principal(1));
+
+        assert!(service.reserve_purchase(0, 11).is_err());
+        let purchase = service.reserve_purchase(0, 4).ok().unwrap();
+        assert_eq!(purchase.cost, 400);
+        assert_eq!(service.spaces[&0].details.units_available, 6);
+        assert!(service.reserve_purchase(0, 7).is_err());
+
+        service.cancel_purchase(&purchase);
+        assert_eq!(service.spaces[&0].details.units_available, 10);
+        assert_eq!(
+            HousingDaoService::voting_power(&principal(1), &holdings(&service)),
+            0
+        );
+
+        let purchase = service.reserve_purchase(0, 5).ok().unwrap();
+        service.complete_purchase(&purchase);
+        assert_eq!(
+            HousingDaoService::voting_power(&principal(1), &holdings(&service)),
+            5
+        );
+
+        //a refund that could not be paid is kept until an admin takes it to retry
+        service.record_owed_refund(&purchase, String::from("ledger unavailable"));
+        assert!(service.take_owed_refunds().is_err());
+        service
+            .roles
+            .insert(principal(0), HashSet::from([Role::Admin]));
+        set_caller(&mut service, principal(0));
+        let refunds = service.take_owed_refunds().ok().unwrap();
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].amount_e8s, 500);
+        assert!(service.owed_refunds.is_empty());
+    }
+
+    #[test]
+    fn income_is_split_by_units_with_dust_to_largest_remainders() {
+        let holding = |token_id, num_units| SpaceHolding {
+            token_id,
+            owner: principal(token_id as u8),
+            num_units,
+        };
+        let holdings = vec![holding(1, 1), holding(2, 1), holding(3, 1)];
+        assert_eq!(split_income(100, &holdings), Some(vec![34, 33, 33]));
+        assert_eq!(split_income(2, &holdings), Some(vec![1, 1, 0]));
+
+        let holdings = vec![holding(1, 1), holding(2, 2), holding(3, 4)];
+        assert_eq!(split_income(10, &holdings), Some(vec![1, 3, 6]));
+        assert_eq!(split_income(10, &[holding(1, 0)]), None);
+        assert_eq!(split_income(10, &[]), None);
+
+        let mut service = service_with_holders(&[]);
+        set_caller(&mut service, principal(1));
+        assert!(service.begin_distribution(0, 100).is_err());
+        set_caller(&mut service, principal(0));
+        assert!(service.begin_distribution(0, 0).is_err());
+        assert!(service.begin_distribution(1, 100).is_err());
+        assert_eq!(service.begin_distribution(0, 100).ok(), Some(0));
+        assert_eq!(service.begin_distribution(0, 100).ok(), Some(1));
+
+        //a property manager can pay out a space it does not own
+        service
+            .roles
+            .insert(principal(1), HashSet::from([Role::PropertyManager]));
+        set_caller(&mut service, principal(1));
+        assert_eq!(service.begin_distribution(0, 100).ok(), Some(2));
+    }
+
+    #[test]
+    fn failed_payouts_are_taken_once_for_retry() {
+        let mut service = service_with_holders(&[]);
+        let payout = |token_id, status| Payout {
+            distribution_id: 4,
+            space_id: 0,
+            token_id,
+            owner: principal(1),
+            num_units: 1,
+            amount_e8s: 10,
+            timestamp: 0,
+            status,
+        };
+        service.record_payouts(&[
+            payout(1, PayoutStatus::Paid { block_index: 3 }),
+            payout(2, PayoutStatus::Failed(String::from("down"))),
+        ]);
+
+        set_caller(&mut service, principal(1));
+        assert!(service.take_failed_payouts(4).is_err());
+        set_caller(&mut service, principal(0));
+        assert!(service.take_failed_payouts(5).is_err());
+
+        let failed = service.take_failed_payouts(4).ok().unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].token_id, 2);
+        //a second retry while the first is in flight finds nothing to pay
+        assert_eq!(service.take_failed_payouts(4).ok().unwrap().len(), 0);
+
+        service.settle_payout(4, 2, PayoutStatus::Paid { block_index: 9 });
+        assert_eq!(service.income_statement(principal(1)).total_e8s, 20);
+    }
+
+    #[test]
+    fn income_statement_totals_paid_payouts_per_space() {
+        let mut service = HousingDaoService::default();
+        let payout = |distribution_id, space_id, owner, amount_e8s, status| Payout {
+            distribution_id,
+            space_id,
+            token_id: space_id + 10,
+            owner,
+            num_units: 2,
+            amount_e8s,
+            timestamp: distribution_id,
+            status,
+        };
+        let paid = |block_index| PayoutStatus::Paid { block_index };
+        service.record_payouts(&[
+            payout(0, 2, principal(1), 50, paid(7)),
+            payout(0, 2, principal(2), 50, paid(8)),
+            payout(1, 1, principal(1), 30, paid(9)),
+            payout(
+                2,
+                2,
+                principal(1),
+                40,
+                PayoutStatus::Failed(String::from("down")),
+            ),
+            payout(3, 2, principal(1), 20, paid(11)),
+        ]);
+
+        let statement = service.income_statement(principal(1));
+        assert_eq!(statement.total_e8s, 100);
+        let space_ids: Vec<u64> = statement.spaces.iter().map(|s| s.space_id).collect();
+        assert_eq!(space_ids, vec![1, 2]);
+        assert_eq!(statement.spaces[1].total_e8s, 70);
+        let cumulative: Vec<u64> = statement.spaces[1]
+            .lines
+            .iter()
+            .map(|l| l.cumulative_e8s)
+            .collect();
+        assert_eq!(cumulative, vec![50, 50, 70]);
+
+        let csv = service.income_statement_csv(principal(1));
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[1], "1,1,11,1,2,30,paid,9,30");
+        assert_eq!(rows[3], "2,2,12,2,2,40,failed,,50");
+        assert_eq!(service.income_statement(principal(3)).spaces, vec![]);
+    }
+
+    #[test]
+    fn accounts_are_registered_for_the_caller() {
+        let mut service = HousingDaoService::default();
+        set_caller(&mut service, Principal::anonymous());
+        assert!(service.create_account().is_err());
+
+        set_caller(&mut service, principal(1));
+        assert_eq!(service.create_account().ok(), Some(principal(1)));
+        assert!(service.create_account().is_err());
+        assert!(service.accounts[&principal(1)].housing_units.is_empty());
+        assert_eq!(service.get_roles(&principal(1)), vec![Role::Investor]);
+    }
+
+    #[test]
+    fn only_auditors_list_the_kyc_status_of_accounts() {
+        let mut service = service_with_holders(&[(principal(1), 2), (principal(2), 2)]);
+        service
+            .roles
+            .insert(principal(3), HashSet::from([Role::Auditor]));
+
+        set_caller(&mut service, principal(1));
+        assert!(service.list_accounts().is_err());
+        assert!(service.get_account_details().is_ok());
+        set_caller(&mut service, Principal::anonymous());
+        assert!(service.list_accounts().is_err());
+
+        set_caller(&mut service, principal(3));
+        let accounts = service.list_accounts().ok().unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert!(accounts
+            .iter()
+            .all(|account| matches!(account.kyc, KycStatus::Verified { .. })));
+    }
+
+    #[test]
+    fn unverified_holders_cannot_buy_or_vote() {
+        let mut service = service_with_holders(&[(principal(1), 2), (principal(2), 2)]);
+        let proposal_id = {
+            set_env(&mut service, principal(1), 10);
+            service
+                .submit_proposal(
+                    0,
+                    Proposition::Other(String::from("paint")),
+                    &holdings(&service),
+                )
+                .ok()
+                .unwrap()
+        };
+
+        set_caller(&mut service, principal(1));
+        assert!(service
+            .check_kyc_status(&principal(2), &KycStatus::Rejected)
+            .is_err());
+        service
+            .roles
+            .insert(principal(1), HashSet::from([Role::Auditor]));
+        assert!(service
+            .check_kyc_status(&principal(3), &KycStatus::Pending)
+            .is_err());
+        let verified = KycStatus::Verified {
+            jurisdiction: String::from("NG"),
+            expires_at: 20,
+        };
+        assert_eq!(
+            service.check_kyc_status(&principal(2), &verified).ok(),
+            Some(Some(20))
+        );
+        //checking leaves the account as it was until the update is applied
+        assert_ne!(service.accounts[&principal(2)].kyc, verified);
+        service.apply_kyc_status(&principal(2), verified);
+
+        set_env(&mut service, principal(2), 20);
+        assert!(matches!(
+            service
+                .vote(proposal_id, Vote::Yes, &holdings(&service))
+                .err()
+                .unwrap()
+                .error_type,
+            ErrorType::NotVerified(_)
+        ));
+        assert!(service.reserve_purchase(0, 1).is_err());
+
+        set_env(&mut service, principal(2), 19);
+        assert!(service.reserve_purchase(0, 1).is_ok());
+        assert!(service
+            .vote(proposal_id, Vote::Yes, &holdings(&service))
+            .is_ok());
+    }
+
+    #[test]
+    fn only_admins_manage_roles() {
+        let mut service = HousingDaoService::default();
+        service
+            .roles
+            .insert(principal(1), HashSet::from([Role::Admin]));
+
+        set_caller(&mut service, principal(2));
+        assert!(service.grant_role(principal(2), Role::Admin).is_err());
+        assert!(service.require_role(Role::PropertyManager).is_err());
+
+        set_caller(&mut service, principal(1));
+        assert!(service.require_role(Role::PropertyManager).is_ok());
+        assert!(service
+            .grant_role(principal(2), Role::PropertyManager)
+            .is_ok());
+        assert!(service.revoke_role(principal(1), Role::Admin).is_err());
+        assert!(service.grant_role(principal(2), Role::Admin).is_ok());
+        assert!(service.revoke_role(principal(1), Role::Admin).is_ok());
+        assert!(service.grant_role(principal(3), Role::Auditor).is_err());
+
+        set_caller(&mut service, principal(2));
+        assert!(service.require_role(Role::PropertyManager).is_ok());
+        assert!(service
+            .revoke_role(principal(2), Role::PropertyManager)
+            .is_ok());
+        assert_eq!(service.get_roles(&principal(2)), vec![Role::Admin]);
+        assert_eq!(service.get_roles(&principal(1)), vec![]);
+    }
+
+    #[test]
+    fn search_units_filters_sorts_and_pages() {
+        let mut service = HousingDaoService::default();
+        for (id, location, price, units) in [
+            (1, "Lekki, Lagos", 300, 5),
+            (2, "Abuja", 100, 20),
+            (3, "Ikeja, LAGOS", 200, 0),
+            (4, "Yaba, Lagos", 100, 8),
+        ] {
+            service.spaces.insert(
+                id,
+                Space {
+                    id,
+                    details: SpaceDetails {
+                        owner: principal(id as u8),
+                        location: String::from(location),
+                        description: String::new(),
+                        price_per_unit: price,
+                        units_available: units,
+                    },
+                },
+            );
+        }
+
+        let filter = SpaceFilter {
+            location: Some(String::from("lagos")),
+            min_units_available: Some(1),
+            ..Default::default()
+        };
+        let page = service.search_units(filter.clone(), SpaceSort::PriceAsc, 0, 1);
+        assert_eq!(page.total, 2);
+        assert_eq!(
+            page.spaces.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![4]
+        );
+        let page = service.search_units(filter, SpaceSort::PriceAsc, 1, 1);
+        assert_eq!(
+            page.spaces.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![1]
+        );
+
+        let filter = SpaceFilter {
+            max_price_per_unit: Some(200),
+            ..Default::default()
+        };
+        let page = service.search_units(filter, SpaceSort::PriceDesc, 0, 10);
+        assert_eq!(
+            page.spaces.iter().map(|s| s.id).collect::<Vec<_>>(),
+            vec![3, 2, 4]
+        );
+    }
 }
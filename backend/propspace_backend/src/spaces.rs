@@ -0,0 +1,104 @@
+use crate::dip721::DIP721Service;
+use crate::payments::PaymentsService;
+use crate::types::*;
+use crate::SERVICE;
+
+//the dip721 canister assigns space ids so both canisters refer to a property by the same id
+pub async fn create_space(details: SpaceDetails) -> Result<u64, DaoServiceError> {
+    let (owner, dip_service) = SERVICE.with(|service| {
+        let service = service.borrow();
+        let owner = service.authorize_space_creation()?;
+        Ok::<_, DaoServiceError>((owner, DIP721Service::from(service.dip_service_principal)))
+    })?;
+
+    let space_id = dip_service
+        .create_space(details.price_per_unit, details.units_available)
+        .await?;
+
+    SERVICE.with(|service| {
+        service
+            .borrow_mut()
+            .register_space(owner, space_id, details)
+    })
+}
+
+//the buyer's payment is collected before the token is minted and returned if the mint fails,
+//the buyer must have approved this canister to spend the cost plus the ledger fee
+pub async fn purchase_units(space_id: u64, num_units: u64) -> Result<u64, DaoServiceError> {
+    let (purchase, dip_service, payments_service, treasury) = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let purchase = service.reserve_purchase(space_id, num_units)?;
+        Ok::<_, DaoServiceError>((
+            purchase,
+            DIP721Service::from(service.dip_service_principal),
+            PaymentsService::from(service.payments_service_principal),
+            service.env.canister_id(),
+        ))
+    })?;
+
+    if let Err(err) = payments_service
+        .transfer_from(purchase.buyer, treasury, purchase.cost, None)
+        .await
+    {
+        SERVICE.with(|service| service.borrow_mut().cancel_purchase(&purchase));
+        return Err(err);
+    }
+
+    let minted = dip_service
+        .mint_token(
+            purchase.buyer,
+            space_id,
+            purchase.details.clone(),
+            None,
+            num_units,
+        )
+        .await;
+
+    let token_id = match minted {
+        Ok(token_id) => token_id,
+        Err(err) => {
+            SERVICE.with(|service| service.borrow_mut().cancel_purchase(&purchase));
+            //a refund the ledger rejects is kept so an admin can pay it later
+            if let Err(refund_err) = payments_service
+                .transfer(purchase.buyer, purchase.cost, None)
+                .await
+            {
+                SERVICE.with(|service| {
+                    service
+                        .borrow_mut()
+                        .record_owed_refund(&purchase, format!("{:?}", refund_err.error_type))
+                });
+            }
+            return Err(err);
+        }
+    };
+
+    SERVICE.with(|service| service.borrow_mut().complete_purchase(&purchase));
+    Ok(token_id)
+}
+
+//pays the refunds owed for failed purchases, returns the ones that failed again
+pub async fn retry_refunds() -> Result<Vec<OwedRefund>, DaoServiceError> {
+    let (refunds, payments_service) = SERVICE.with(|service| {
+        let mut service = service.borrow_mut();
+        let refunds = service.take_owed_refunds()?;
+        Ok::<_, DaoServiceError>((
+            refunds,
+            PaymentsService::from(service.payments_service_principal),
+        ))
+    })?;
+
+    let mut still_owed = vec![];
+    for mut refund in refunds {
+        if let Err(err) = payments_service
+            .transfer(refund.buyer, refund.amount_e8s, None)
+            .await
+        {
+            refund.reason = format!("{:?}", err.error_type);
+            //recorded straight away so a later trap does not lose it
+            SERVICE.with(|service| service.borrow_mut().owed_refunds.push(refund.clone()));
+            still_owed.push(refund);
+        }
+    }
+    Ok(still_owed)
+}
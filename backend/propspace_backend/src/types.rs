@@ -1,5 +1,6 @@
 use crate::dip721::NftError;
 use crate::env::{EmptyEnvironment, Environment};
+use crate::payments::{TransferError, TransferFromError};
 use std::collections::HashMap;
 
 use ic_cdk::export::{
@@ -15,8 +16,22 @@ pub struct Tokens {
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Account {
     pub principal: Principal,
-    pub tokens: Tokens,
     pub housing_units: Vec<HousingUnit>,
+    pub kyc: KycStatus,
+}
+
+//identity verification of an account holder, set by auditors
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub enum KycStatus {
+    #[default]
+    Unverified,
+    Pending,
+    Verified {
+        jurisdiction: String,
+        //nanoseconds since the epoch after which the holder must be verified again
+        expires_at: u64,
+    },
+    Rejected,
 }
 
 #[derive(Clone, Copy, Debug, Default, CandidType, Deserialize)]
@@ -32,12 +47,48 @@ pub struct Proposal {
     pub timestamp: u64,
     pub housing_unit: u64,
     pub proposer: Principal,
-    pub proposition: String,
+    pub proposition: Proposition,
     pub state: ProposalState,
     pub percentage_for: f64,
     pub percentage_against: f64,
     pub percentage_abstain: f64,
     pub voters: Vec<Principal>,
+    //units each token of the space held when the proposal was submitted, votes are weighed
+    //against them so units moved to another holder after submission are not counted twice
+    pub token_weights: Vec<TokenWeight>,
+    pub total_units: u64,
+    //tokens already voted with
+    pub voted_tokens: Vec<u64>,
+    //time after which the proposal is closed and its outcome computed
+    pub deadline: u64,
+    //minimum percentage of units that must vote for the outcome to count
+    pub quorum: f64,
+    //percentage of yes among yes and no votes that must be exceeded to accept
+    pub threshold: f64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
+pub struct TokenWeight {
+    pub token_id: u64,
+    pub num_units: u64,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
+pub struct ProposalConfig {
+    //voting period in nanoseconds
+    pub voting_period: u64,
+    pub quorum: f64,
+    pub threshold: f64,
+}
+
+impl Default for ProposalConfig {
+    fn default() -> Self {
+        Self {
+            voting_period: 7 * 24 * 60 * 60 * 1_000_000_000,
+            quorum: 50.0,
+            threshold: 50.0,
+        }
+    }
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize, PartialEq, PartialOrd)]
@@ -55,24 +106,130 @@ pub struct SpaceDetails {
     pub units_available: u64,
 }
 
+//criteria for search_units, unset fields match every space
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct SpaceFilter {
+    //case-insensitive substring of the location
+    pub location: Option<String>,
+    pub min_price_per_unit: Option<u64>,
+    pub max_price_per_unit: Option<u64>,
+    pub min_units_available: Option<u64>,
+    pub owner: Option<Principal>,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq)]
+pub enum SpaceSort {
+    IdAsc,
+    PriceAsc,
+    PriceDesc,
+    UnitsAvailableDesc,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SpacePage {
+    pub spaces: Vec<Space>,
+    //number of spaces matching the filter across all pages
+    pub total: u64,
+}
+
+//units held back for a buyer while the token is minted and paid for
+#[derive(Clone, Debug)]
+pub struct PendingPurchase {
+    pub buyer: Principal,
+    pub space_id: u64,
+    pub num_units: u64,
+    pub cost: u64,
+    pub details: SpaceDetails,
+}
+
+//a purchase payment that could not be returned after its mint failed, kept until an admin retries it
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct OwedRefund {
+    pub buyer: Principal,
+    pub space_id: u64,
+    pub num_units: u64,
+    pub amount_e8s: u64,
+    pub timestamp: u64,
+    //why the last refund attempt failed
+    pub reason: String,
+}
+
+//one token's share of an income distribution
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Payout {
+    pub distribution_id: u64,
+    pub space_id: u64,
+    pub token_id: u64,
+    pub owner: Principal,
+    pub num_units: u64,
+    pub amount_e8s: u64,
+    pub timestamp: u64,
+    pub status: PayoutStatus,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PayoutStatus {
+    //recorded before the transfer is made, left pending if the distribution is interrupted
+    Pending,
+    Paid { block_index: u64 },
+    //the share stays in the canister's ledger account until the payout is retried
+    Failed(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct IncomeStatementLine {
+    pub distribution_id: u64,
+    pub token_id: u64,
+    pub timestamp: u64,
+    //units the token held when the income was distributed
+    pub num_units: u64,
+    pub amount_e8s: u64,
+    pub status: PayoutStatus,
+    //paid income from the space up to and including this payout
+    pub cumulative_e8s: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct SpaceIncome {
+    pub space_id: u64,
+    pub lines: Vec<IncomeStatementLine>,
+    pub total_e8s: u64,
+}
+
+//payouts received by one principal, failed payouts are listed but not counted in the totals
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct IncomeStatement {
+    pub owner: Principal,
+    pub spaces: Vec<SpaceIncome>,
+    pub total_e8s: u64,
+}
+
 //struct to preserve and export dao data - to allow easy reuse
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct HousingDaoStorage {
     pub dip_service_principal: Principal,
+    pub payments_service_principal: Principal,
     pub accounts: Vec<Account>,
     pub proposals: Vec<Proposal>,
     pub spaces: Vec<Space>,
-    pub secret_key: String,
+    pub roles: Vec<(Principal, Vec<Role>)>,
+    pub proposal_config: ProposalConfig,
+    pub payouts: Vec<Payout>,
+    pub owed_refunds: Vec<OwedRefund>,
 }
 
 impl Default for HousingDaoStorage {
     fn default() -> Self {
         Self {
             dip_service_principal: Principal::anonymous(),
+            payments_service_principal: Principal::anonymous(),
             accounts: vec![],
             proposals: vec![],
             spaces: vec![],
-            secret_key: String::from("default_secret"),
+            roles: vec![],
+            proposal_config: ProposalConfig::default(),
+            payouts: vec![],
+            owed_refunds: vec![],
         }
     }
 }
@@ -87,6 +244,22 @@ pub enum ProposalState {
     Failed(String),
 }
 
+//admins pass every role check
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Role {
+    Admin,
+    PropertyManager,
+    Investor,
+    Auditor,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
+pub enum Vote {
+    Yes,
+    No,
+    Abstain,
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
 pub enum Proposition {
     UnitsSale(UnitSaleProposition),
@@ -96,25 +269,30 @@ pub enum Proposition {
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
 pub struct UnitSaleProposition {
-    num_units: u64,
-    buyer_account: Principal,
+    //token of the proposer the units are sold from
+    pub token_id: u64,
+    pub num_units: u64,
+    pub buyer_account: Principal,
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize, PartialEq, PartialOrd)]
 pub struct SetPriceProposition {
-    new_price: u64,
+    pub new_price: u64,
 }
 
-#[derive(Clone, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct DaoServiceError {
     pub error_type: ErrorType,
 }
 
-#[derive(Clone, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum ErrorType {
     Unauthorized(String),
+    NotVerified(String),
     NotFound(String),
     Failure(String),
     NftError(NftError),
+    TransferError(TransferError),
+    TransferFromError(TransferFromError),
     CanisterError(String),
 }
@@ -0,0 +1,599 @@
+use crate::{GenericValue, State, TxEvent, STATE};
+use candid::{define_function, CandidType, Int, Nat, Principal};
+use ic_cdk::{api, query};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+const MAX_BLOCKS_PER_RESPONSE: u64 = 100;
+
+type Hash = [u8; 32];
+
+//icrc-3 value
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Blob(Vec<u8>),
+    Text(String),
+    Nat(Nat),
+    Int(Int),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+//icrc-3 has no booleans or floats, booleans become 0 or 1 and floats their decimal text
+impl From<GenericValue> for Value {
+    fn from(value: GenericValue) -> Self {
+        match value {
+            GenericValue::BoolContent(b) => Value::Nat(Nat::from(b as u8)),
+            GenericValue::TextContent(t) => Value::Text(t),
+            GenericValue::BlobContent(b) => Value::Blob(b),
+            GenericValue::Principal(p) => Value::Blob(p.as_slice().to_vec()),
+            GenericValue::Nat8Content(n) => Value::Nat(Nat::from(n)),
+            GenericValue::Nat16Content(n) => Value::Nat(Nat::from(n)),
+            GenericValue::Nat32Content(n) => Value::Nat(Nat::from(n)),
+            GenericValue::Nat64Content(n) => Value::Nat(Nat::from(n)),
+            GenericValue::NatContent(n) => Value::Nat(n),
+            GenericValue::Int8Content(i) => Value::Int(Int::from(i)),
+            GenericValue::Int16Content(i) => Value::Int(Int::from(i)),
+            GenericValue::Int32Content(i) => Value::Int(Int::from(i)),
+            GenericValue::Int64Content(i) => Value::Int(Int::from(i)),
+            GenericValue::IntContent(i) => Value::Int(i),
+            GenericValue::FloatContent(f) => Value::Text(f.to_string()),
+            GenericValue::NestedContent(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl Value {
+    //representation independent hash from the icrc-3 spec
+    pub(crate) fn hash(&self) -> Hash {
+        let mut hasher = Sha256::new();
+        match self {
+            Value::Blob(bytes) => hasher.update(bytes),
+            Value::Text(text) => hasher.update(text.as_bytes()),
+            Value::Nat(nat) => hasher.update(leb128(nat)),
+            Value::Int(int) => {
+                let mut bytes = vec![];
+                int.encode(&mut bytes)
+                    .expect("writing to a vec cannot fail");
+                hasher.update(bytes)
+            }
+            Value::Array(values) => {
+                for value in values {
+                    hasher.update(value.hash());
+                }
+            }
+            Value::Map(entries) => {
+                let mut entries: Vec<Vec<u8>> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let mut entry = Sha256::digest(key.as_bytes()).to_vec();
+                        entry.extend_from_slice(&value.hash());
+                        entry
+                    })
+                    .collect();
+                entries.sort();
+                for entry in entries {
+                    hasher.update(entry);
+                }
+            }
+        }
+        hasher.finalize().into()
+    }
+}
+
+fn leb128(nat: &Nat) -> Vec<u8> {
+    let mut bytes = vec![];
+    nat.encode(&mut bytes)
+        .expect("writing to a vec cannot fail");
+    bytes
+}
+
+//icrc accounts are encoded as an array holding the owner and, if any, the subaccount
+fn account(principal: Principal) -> Value {
+    Value::Array(vec![Value::Blob(principal.as_slice().to_vec())])
+}
+
+//icrc-7 and icrc-37 operations use their standard block types, units trades and space creation
+//have propspace block types documented in the readme
+const PROPSPACE_BLOCK_TYPES_URL: &str =
+    "https://github.com/abundance-io/propspace#icrc-3-block-types";
+const BLOCK_TYPES: [(&str, &str); 10] = [
+    (
+        "7mint",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7",
+    ),
+    (
+        "7burn",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7",
+    ),
+    (
+        "7xfer",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-7",
+    ),
+    (
+        "37approve",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37",
+    ),
+    (
+        "37approve_coll",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37",
+    ),
+    (
+        "37revoke",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37",
+    ),
+    (
+        "37revoke_coll",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37",
+    ),
+    (
+        "37xfer",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-37",
+    ),
+    ("propspace_trade_units", PROPSPACE_BLOCK_TYPES_URL),
+    ("propspace_create_space", PROPSPACE_BLOCK_TYPES_URL),
+];
+
+fn block_type(event: &TxEvent) -> &'static str {
+    match event.operation.as_str() {
+        "mint" => "7mint",
+        "burn" => "7burn",
+        "transfer" => "7xfer",
+        "transferFrom" => "37xfer",
+        "approve" => "37approve",
+        "revoke" => "37revoke",
+        "setApprovalForAll" => {
+            let approved = event.details.iter().any(|(key, value)| {
+                key == "is_approved" && matches!(value, GenericValue::BoolContent(true))
+            });
+            if approved {
+                "37approve_coll"
+            } else {
+                "37revoke_coll"
+            }
+        }
+        "tradeUnits" => "propspace_trade_units",
+        "createSpace" => "propspace_create_space",
+        //an operation without a block type traps when it is recorded, so the update adding it is
+        //rolled back instead of logging it under another type
+        operation => panic!("operation {} has no icrc-3 block type", operation),
+    }
+}
+
+//the tx fields are named as in the standard of the block type
+fn block(event: &TxEvent, parent_hash: Option<&Vec<u8>>) -> Value {
+    let btype = block_type(event);
+    let caller_key = match btype {
+        "37xfer" => "spender",
+        "37approve" | "37approve_coll" | "37revoke" | "37revoke_coll" => "from",
+        _ => "caller",
+    };
+    let mut tx = vec![(String::from(caller_key), account(event.caller))];
+    for (key, value) in event.details.iter() {
+        let key = match key.as_str() {
+            "token_identifier" => String::from("tid"),
+            "operator" => String::from("spender"),
+            //carried by the block type
+            "is_approved" => continue,
+            key => key.to_owned(),
+        };
+        let value = match value {
+            GenericValue::Principal(principal) => account(*principal),
+            value => Value::from(value.clone()),
+        };
+        tx.push((key, value));
+    }
+
+    let mut block = vec![
+        (String::from("btype"), Value::Text(btype.to_owned())),
+        (String::from("ts"), Value::Nat(Nat::from(event.time))),
+        (String::from("tx"), Value::Map(tx)),
+    ];
+    if let Some(parent_hash) = parent_hash {
+        block.push((String::from("phash"), Value::Blob(parent_hash.clone())));
+    }
+    Value::Map(block)
+}
+
+//the subset of the ic hash tree needed to certify the tip
+enum HashTree {
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(&'static str, Box<HashTree>),
+    Leaf(Vec<u8>),
+}
+
+fn domain_hash(domain: &str) -> Sha256 {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    hasher
+}
+
+fn cbor_header(major: u8, len: usize, out: &mut Vec<u8>) {
+    match len {
+        0..=23 => out.push(major << 5 | len as u8),
+        24..=0xff => out.extend([major << 5 | 24, len as u8]),
+        0x100..=0xffff => {
+            out.push(major << 5 | 25);
+            out.extend((len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(major << 5 | 26);
+            out.extend((len as u32).to_be_bytes());
+        }
+    }
+}
+
+fn cbor_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    cbor_header(2, bytes.len(), out);
+    out.extend_from_slice(bytes);
+}
+
+impl HashTree {
+    fn tip(last_block_index: u64, last_block_hash: &[u8]) -> Self {
+        HashTree::Fork(
+            Box::new(HashTree::Labeled(
+                "last_block_hash",
+                Box::new(HashTree::Leaf(last_block_hash.to_vec())),
+            )),
+            Box::new(HashTree::Labeled(
+                "last_block_index",
+                Box::new(HashTree::Leaf(leb128(&Nat::from(last_block_index)))),
+            )),
+        )
+    }
+
+    fn root_hash(&self) -> Hash {
+        match self {
+            HashTree::Fork(left, right) => {
+                let mut hasher = domain_hash("ic-hashtree-fork");
+                hasher.update(left.root_hash());
+                hasher.update(right.root_hash());
+                hasher.finalize().into()
+            }
+            HashTree::Labeled(label, tree) => {
+                let mut hasher = domain_hash("ic-hashtree-labeled");
+                hasher.update(label.as_bytes());
+                hasher.update(tree.root_hash());
+                hasher.finalize().into()
+            }
+            HashTree::Leaf(bytes) => {
+                let mut hasher = domain_hash("ic-hashtree-leaf");
+                hasher.update(bytes);
+                hasher.finalize().into()
+            }
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            HashTree::Fork(left, right) => {
+                cbor_header(4, 3, out);
+                cbor_header(0, 1, out);
+                left.encode(out);
+                right.encode(out);
+            }
+            HashTree::Labeled(label, tree) => {
+                cbor_header(4, 3, out);
+                cbor_header(0, 2, out);
+                cbor_bytes(label.as_bytes(), out);
+                tree.encode(out);
+            }
+            HashTree::Leaf(bytes) => {
+                cbor_header(4, 2, out);
+                cbor_header(0, 3, out);
+                cbor_bytes(bytes, out);
+            }
+        }
+    }
+
+    //cbor with the self describing tag, as the ic serves hash trees
+    fn to_cbor(&self) -> Vec<u8> {
+        let mut out = vec![0xd9, 0xd9, 0xf7];
+        self.encode(&mut out);
+        out
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct GetBlocksArgs {
+    start: Nat,
+    length: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct BlockWithId {
+    id: Nat,
+    block: Value,
+}
+
+define_function!(pub(crate) GetBlocksCallback : (Vec<GetBlocksArgs>) -> (GetBlocksResult) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct ArchivedBlocks {
+    args: Vec<GetBlocksArgs>,
+    callback: GetBlocksCallback,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct GetBlocksResult {
+    log_length: Nat,
+    blocks: Vec<BlockWithId>,
+    archived_blocks: Vec<ArchivedBlocks>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct GetArchivesArgs {
+    from: Option<Principal>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct ArchiveInfo {
+    canister_id: Principal,
+    start: Nat,
+    end: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct DataCertificate {
+    certificate: Vec<u8>,
+    hash_tree: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct BlockType {
+    block_type: String,
+    url: String,
+}
+
+impl State {
+    //hashes the blocks of the transactions that don't have one yet and certifies the new tip
+    pub(crate) fn append_block(&mut self) {
+        while self.block_hashes.len() < self.transactions.len() {
+            let id = self.block_hashes.len();
+            let hash = block(&self.transactions[id], self.block_hashes.last()).hash();
+            self.block_hashes.push(hash.to_vec());
+        }
+        self.certify_tip();
+    }
+
+    //the hashes are derived from the transaction log, so they are rebuilt after an upgrade in
+    //case the log was saved without them or blocks are now built differently
+    pub(crate) fn rebuild_block_hashes(&mut self) {
+        self.block_hashes.clear();
+        self.append_block();
+    }
+
+    fn block(&self, id: u64) -> Option<Value> {
+        let event = self.transactions.get(id as usize)?;
+        let parent_hash = id
+            .checked_sub(1)
+            .and_then(|parent| self.block_hashes.get(parent as usize));
+        Some(block(event, parent_hash))
+    }
+
+    fn tip_tree(&self) -> Option<HashTree> {
+        let last_block_hash = self.block_hashes.last()?;
+        Some(HashTree::tip(
+            self.block_hashes.len() as u64 - 1,
+            last_block_hash,
+        ))
+    }
+
+    pub(crate) fn certify_tip(&self) {
+        if let Some(tree) = self.tip_tree() {
+            if cfg!(target_arch = "wasm32") {
+                api::set_certified_data(&tree.root_hash());
+            }
+        }
+    }
+
+    fn get_blocks(&self, args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+        let log_length = self.block_hashes.len() as u64;
+        let mut budget = MAX_BLOCKS_PER_RESPONSE;
+        let mut blocks = vec![];
+        for arg in args {
+            let (Ok(start), Ok(length)) =
+                (u64::try_from(&arg.start.0), u64::try_from(&arg.length.0))
+            else {
+                continue;
+            };
+            let end = start.saturating_add(length.min(budget)).min(log_length);
+            for id in start..end {
+                if let Some(block) = self.block(id) {
+                    blocks.push(BlockWithId {
+                        id: Nat::from(id),
+                        block,
+                    });
+                    budget -= 1;
+                }
+            }
+        }
+        GetBlocksResult {
+            log_length: Nat::from(log_length),
+            blocks,
+            archived_blocks: vec![],
+        }
+    }
+}
+
+#[query]
+fn icrc3_get_blocks(args: Vec<GetBlocksArgs>) -> GetBlocksResult {
+    STATE.with_borrow(|state| state.get_blocks(args))
+}
+
+//every block stays in this canister
+#[query]
+fn icrc3_get_archives(_args: GetArchivesArgs) -> Vec<ArchiveInfo> {
+    vec![]
+}
+
+#[query]
+fn icrc3_get_tip_certificate() -> Option<DataCertificate> {
+    let certificate = api::data_certificate()?;
+    STATE.with_borrow(|state| {
+        state.tip_tree().map(|tree| DataCertificate {
+            certificate,
+            hash_tree: tree.to_cbor(),
+        })
+    })
+}
+
+#[query]
+fn icrc3_supported_block_types() -> Vec<BlockType> {
+    BLOCK_TYPES
+        .into_iter()
+        .map(|(block_type, url)| BlockType {
+            block_type: block_type.to_owned(),
+            url: url.to_owned(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{mint_args, principal};
+
+    fn hex(hash: Hash) -> String {
+        hash.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn values_hash_like_the_icrc3_spec() {
+        assert_eq!(
+            hex(Value::Nat(Nat::from(42u64)).hash()),
+            "684888c0ebb17f374298b65ee2807526c066094c701bcc7ebbe1c1095f494fc1"
+        );
+        assert_eq!(
+            hex(Value::Array(vec![
+                Value::Nat(Nat::from(3u64)),
+                Value::Text(String::from("foo")),
+                Value::Blob(vec![5, 6]),
+            ])
+            .hash()),
+            "514a04011caa503990d446b7dec5d79e19c221ae607fb08b2848c67734d468d6"
+        );
+    }
+
+    #[test]
+    fn blocks_chain_to_their_parent() {
+        let mut state = State::default();
+        state.create_space(
+            crate::Space {
+                id: 1,
+                price_per_unit: 10,
+                num_units_available: 5,
+            },
+            principal(0),
+            1,
+        );
+        assert!(state
+            .mint(mint_args(principal(1), 1, 2), principal(0), 2)
+            .is_ok());
+
+        let result = state.get_blocks(vec![GetBlocksArgs {
+            start: Nat::from(0u64),
+            length: Nat::from(10u64),
+        }]);
+        assert_eq!(result.log_length, Nat::from(2u64));
+        assert_eq!(result.blocks.len(), 2);
+
+        let Value::Map(mint) = &result.blocks[1].block else {
+            panic!("blocks are maps");
+        };
+        assert!(mint.contains(&(String::from("btype"), Value::Text(String::from("7mint")))));
+        assert!(mint.contains(&(
+            String::from("phash"),
+            Value::Blob(result.blocks[0].block.hash().to_vec())
+        )));
+        assert_eq!(
+            state.block_hashes[1],
+            result.blocks[1].block.hash().to_vec()
+        );
+
+        //a log restored without its hashes gets them back
+        let hashes = state.block_hashes.clone();
+        state.block_hashes.truncate(1);
+        state.rebuild_block_hashes();
+        assert_eq!(state.block_hashes, hashes);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no icrc-3 block type")]
+    fn operations_without_a_block_type_are_not_logged() {
+        let mut state = State::default();
+        state.record(principal(0), "rename", vec![], &[], 1);
+    }
+
+    #[test]
+    fn every_block_type_is_supported() {
+        let mut state = State::default();
+        state.create_space(
+            crate::Space {
+                id: 1,
+                price_per_unit: 10,
+                num_units_available: 5,
+            },
+            principal(0),
+            1,
+        );
+        state.verified_until.insert(principal(1), u64::MAX);
+        state.verified_until.insert(principal(2), u64::MAX);
+        state.canister_metadata.custodians.insert(principal(0));
+        assert!(state
+            .mint(mint_args(principal(1), 1, 2), principal(0), 1)
+            .is_ok());
+        assert!(state
+            .approve(principal(1), principal(3), 1, None, 1)
+            .is_ok());
+        assert!(state
+            .set_approval_for_all(principal(1), principal(3), true, None, 1)
+            .is_ok());
+        assert!(state
+            .set_approval_for_all(principal(1), principal(3), false, None, 1)
+            .is_ok());
+        assert!(state
+            .transfer_from(principal(3), principal(1), principal(2), 1, None, 1)
+            .is_ok());
+        assert!(state
+            .trade_units(1, principal(2), principal(1), 1, principal(2), 1)
+            .is_ok());
+        assert!(state
+            .transfer_from(principal(2), principal(2), principal(1), 1, None, 1)
+            .is_ok());
+        assert!(state.burn(1, principal(0), 1).is_ok());
+
+        let btypes: Vec<&str> = state.transactions.iter().map(block_type).collect();
+        assert_eq!(
+            btypes,
+            vec![
+                "propspace_create_space",
+                "7mint",
+                "37approve",
+                "37approve_coll",
+                "37revoke_coll",
+                "37xfer",
+                "propspace_trade_units",
+                "7xfer",
+                "7burn"
+            ]
+        );
+        for btype in btypes {
+            assert!(BLOCK_TYPES.iter().any(|(supported, _)| *supported == btype));
+        }
+
+        let Value::Map(xfer) = state.block(5).unwrap() else {
+            panic!("blocks are maps");
+        };
+        let Some((_, Value::Map(tx))) = xfer.iter().find(|(key, _)| key == "tx") else {
+            panic!("blocks carry their transaction");
+        };
+        assert_eq!(tx[0], (String::from("spender"), account(principal(3))));
+    }
+}
@@ -0,0 +1,794 @@
+use crate::icrc3::Value;
+use crate::icrc7::{
+    token_id, Account, Rejection, TransferError, TransferResult, DEFAULT_TAKE_VALUE,
+    MAX_QUERY_BATCH_SIZE, MAX_TAKE_VALUE, MAX_UPDATE_BATCH_SIZE,
+};
+use crate::{nat_detail, principal_detail, GenericValue, NftError, State, TokenIdentifier, STATE};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::{api, query, update};
+use serde::Deserialize;
+
+//icrc-37 approvals are the dip721 operators: a token has at most one approved spender, approving
+//another replaces it, and the collection approvals of an owner are its approved-for-all operators
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct ApprovalInfo {
+    spender: Account,
+    from_subaccount: Option<Vec<u8>>,
+    //approvals don't expire, an approval with an expiry is rejected
+    expires_at: Option<u64>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+impl ApprovalInfo {
+    //what is kept of an approval once it is granted
+    fn of(spender: Principal) -> Self {
+        Self {
+            spender: Account::from(spender),
+            from_subaccount: None,
+            expires_at: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct ApproveTokenArg {
+    token_id: Nat,
+    approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum ApproveTokenError {
+    InvalidSpender,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub(crate) type ApproveTokenResult = Result<Nat, ApproveTokenError>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct ApproveCollectionArg {
+    approval_info: ApprovalInfo,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum ApproveCollectionError {
+    InvalidSpender,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub(crate) type ApproveCollectionResult = Result<Nat, ApproveCollectionError>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct RevokeTokenApprovalArg {
+    //none revokes whichever spender is approved
+    spender: Option<Account>,
+    from_subaccount: Option<Vec<u8>>,
+    token_id: Nat,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum RevokeTokenApprovalError {
+    ApprovalDoesNotExist,
+    Unauthorized,
+    NonExistingTokenId,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub(crate) type RevokeTokenApprovalResult = Result<Nat, RevokeTokenApprovalError>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct RevokeCollectionApprovalArg {
+    //none revokes every collection approval of the caller
+    spender: Option<Account>,
+    from_subaccount: Option<Vec<u8>>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum RevokeCollectionApprovalError {
+    ApprovalDoesNotExist,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub(crate) type RevokeCollectionApprovalResult = Result<Nat, RevokeCollectionApprovalError>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct IsApprovedArg {
+    spender: Account,
+    from_subaccount: Option<Vec<u8>>,
+    token_id: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct TokenApproval {
+    token_id: Nat,
+    approval_info: ApprovalInfo,
+}
+
+pub(crate) type CollectionApproval = ApprovalInfo;
+
+//the icrc-37 transfer error has the same variants as the icrc-7 one
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct TransferFromArg {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    token_id: Nat,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+fn generic_error(rejection: Rejection) -> (Nat, String) {
+    match rejection {
+        Rejection::Duplicate { duplicate_of } => (
+            Nat::from(3u64),
+            format!("duplicate of transaction {}", duplicate_of),
+        ),
+        _ => match TransferError::from(rejection) {
+            TransferError::GenericError {
+                error_code,
+                message,
+            } => (error_code, message),
+            _ => (Nat::from(0u64), String::from("rejected")),
+        },
+    }
+}
+
+fn expiry_not_supported() -> (Nat, String) {
+    (
+        Nat::from(4u64),
+        String::from("approvals with an expiry are not supported"),
+    )
+}
+
+impl From<Rejection> for ApproveTokenError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooOld => ApproveTokenError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                ApproveTokenError::CreatedInFuture { ledger_time }
+            }
+            rejection => {
+                let (error_code, message) = generic_error(rejection);
+                ApproveTokenError::GenericError {
+                    error_code,
+                    message,
+                }
+            }
+        }
+    }
+}
+
+impl From<Rejection> for ApproveCollectionError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooOld => ApproveCollectionError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                ApproveCollectionError::CreatedInFuture { ledger_time }
+            }
+            rejection => {
+                let (error_code, message) = generic_error(rejection);
+                ApproveCollectionError::GenericError {
+                    error_code,
+                    message,
+                }
+            }
+        }
+    }
+}
+
+impl From<Rejection> for RevokeTokenApprovalError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooOld => RevokeTokenApprovalError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                RevokeTokenApprovalError::CreatedInFuture { ledger_time }
+            }
+            rejection => {
+                let (error_code, message) = generic_error(rejection);
+                RevokeTokenApprovalError::GenericError {
+                    error_code,
+                    message,
+                }
+            }
+        }
+    }
+}
+
+impl From<Rejection> for RevokeCollectionApprovalError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooOld => RevokeCollectionApprovalError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                RevokeCollectionApprovalError::CreatedInFuture { ledger_time }
+            }
+            rejection => {
+                let (error_code, message) = generic_error(rejection);
+                RevokeCollectionApprovalError::GenericError {
+                    error_code,
+                    message,
+                }
+            }
+        }
+    }
+}
+
+impl From<NftError> for ApproveTokenError {
+    fn from(err: NftError) -> Self {
+        match err {
+            NftError::TokenNotFound => ApproveTokenError::NonExistingTokenId,
+            NftError::UnauthorizedOwner => ApproveTokenError::Unauthorized,
+            NftError::SelfApprove => ApproveTokenError::InvalidSpender,
+            _ => ApproveTokenError::GenericError {
+                error_code: Nat::from(0u64),
+                message: String::from("approval failed"),
+            },
+        }
+    }
+}
+
+//approvals only cover the default subaccount of the spender and of the owner
+fn valid_spender(caller: Principal, spender: &Account) -> bool {
+    spender.is_default() && spender.owner != caller && spender.owner != Principal::anonymous()
+}
+
+fn from_default(from_subaccount: &Option<Vec<u8>>) -> bool {
+    from_subaccount
+        .as_ref()
+        .is_none_or(|s| s.iter().all(|b| *b == 0))
+}
+
+//the take of a paginated query, capped at the maximum
+fn take(take: Option<Nat>) -> usize {
+    take.as_ref()
+        .and_then(token_id)
+        .unwrap_or(DEFAULT_TAKE_VALUE)
+        .min(MAX_TAKE_VALUE) as usize
+}
+
+impl State {
+    fn icrc37_approve_token(
+        &mut self,
+        caller: Principal,
+        arg: ApproveTokenArg,
+        now: u64,
+    ) -> ApproveTokenResult {
+        let info = &arg.approval_info;
+        //approving again is harmless, so approvals are checked against the window but not deduplicated
+        self.deduplicate(
+            caller,
+            "icrc37_approve_tokens",
+            &arg,
+            info.memo.as_ref(),
+            info.created_at_time,
+            now,
+        )?;
+        let token_id = token_id(&arg.token_id).ok_or(ApproveTokenError::NonExistingTokenId)?;
+        if !from_default(&info.from_subaccount) {
+            return Err(ApproveTokenError::Unauthorized);
+        }
+        if !valid_spender(caller, &info.spender) {
+            return Err(ApproveTokenError::InvalidSpender);
+        }
+        if info.expires_at.is_some() {
+            let (error_code, message) = expiry_not_supported();
+            return Err(ApproveTokenError::GenericError {
+                error_code,
+                message,
+            });
+        }
+        Ok(self.approve(
+            caller,
+            info.spender.owner,
+            token_id,
+            arg.approval_info.memo,
+            now,
+        )?)
+    }
+
+    fn icrc37_approve_collection(
+        &mut self,
+        caller: Principal,
+        arg: ApproveCollectionArg,
+        now: u64,
+    ) -> ApproveCollectionResult {
+        let info = &arg.approval_info;
+        self.deduplicate(
+            caller,
+            "icrc37_approve_collection",
+            &arg,
+            info.memo.as_ref(),
+            info.created_at_time,
+            now,
+        )?;
+        if !valid_spender(caller, &info.spender) {
+            return Err(ApproveCollectionError::InvalidSpender);
+        }
+        if !from_default(&info.from_subaccount) || info.expires_at.is_some() {
+            let (error_code, message) = if info.expires_at.is_some() {
+                expiry_not_supported()
+            } else {
+                (
+                    Nat::from(5u64),
+                    String::from("only the default subaccount holds tokens"),
+                )
+            };
+            return Err(ApproveCollectionError::GenericError {
+                error_code,
+                message,
+            });
+        }
+        self.set_approval_for_all(
+            caller,
+            info.spender.owner,
+            true,
+            arg.approval_info.memo,
+            now,
+        )
+        .map_err(|_| ApproveCollectionError::InvalidSpender)
+    }
+
+    fn icrc37_revoke_token_approval(
+        &mut self,
+        caller: Principal,
+        arg: RevokeTokenApprovalArg,
+        now: u64,
+    ) -> RevokeTokenApprovalResult {
+        self.deduplicate(
+            caller,
+            "icrc37_revoke_token_approvals",
+            &arg,
+            arg.memo.as_ref(),
+            arg.created_at_time,
+            now,
+        )?;
+        let token_id =
+            token_id(&arg.token_id).ok_or(RevokeTokenApprovalError::NonExistingTokenId)?;
+        let token = self
+            .live_token(token_id)
+            .map_err(|_| RevokeTokenApprovalError::NonExistingTokenId)?;
+        if token.metadata.owner != caller || !from_default(&arg.from_subaccount) {
+            return Err(RevokeTokenApprovalError::Unauthorized);
+        }
+        let operator = match (token.metadata.operator, &arg.spender) {
+            (Some(operator), None) => operator,
+            (Some(operator), Some(spender))
+                if spender.is_default() && spender.owner == operator =>
+            {
+                operator
+            }
+            _ => return Err(RevokeTokenApprovalError::ApprovalDoesNotExist),
+        };
+
+        if let Some(token) = self.tokens.get_mut(&token_id) {
+            token.metadata.operator = None;
+        }
+        let mut details = vec![
+            principal_detail("operator", operator),
+            nat_detail("token_identifier", token_id),
+        ];
+        if let Some(memo) = arg.memo {
+            details.push((String::from("memo"), GenericValue::BlobContent(memo)));
+        }
+        Ok(self.record(caller, "revoke", details, &[token_id], now))
+    }
+
+    //revoking every collection approval returns the transaction of the last one revoked
+    fn icrc37_revoke_collection_approval(
+        &mut self,
+        caller: Principal,
+        arg: RevokeCollectionApprovalArg,
+        now: u64,
+    ) -> RevokeCollectionApprovalResult {
+        self.deduplicate(
+            caller,
+            "icrc37_revoke_collection_approvals",
+            &arg,
+            arg.memo.as_ref(),
+            arg.created_at_time,
+            now,
+        )?;
+        if !from_default(&arg.from_subaccount) {
+            return Err(RevokeCollectionApprovalError::ApprovalDoesNotExist);
+        }
+        let mut operators: Vec<Principal> = match &arg.spender {
+            Some(spender) if spender.is_default() => vec![spender.owner],
+            Some(_) => vec![],
+            None => self
+                .operator_approvals
+                .get(&caller)
+                .map(|operators| operators.iter().copied().collect())
+                .unwrap_or_default(),
+        };
+        operators.sort();
+        if !operators
+            .iter()
+            .all(|operator| self.is_approved_for_all(&caller, operator))
+            || operators.is_empty()
+        {
+            return Err(RevokeCollectionApprovalError::ApprovalDoesNotExist);
+        }
+
+        let mut tx_id = Nat::from(0u64);
+        for operator in operators {
+            tx_id = self
+                .set_approval_for_all(caller, operator, false, arg.memo.clone(), now)
+                .map_err(|_| RevokeCollectionApprovalError::ApprovalDoesNotExist)?;
+        }
+        Ok(tx_id)
+    }
+
+    fn icrc37_is_approved(&self, arg: &IsApprovedArg) -> bool {
+        let token = match token_id(&arg.token_id).and_then(|id| self.live_token(id).ok()) {
+            Some(token) => token,
+            None => return false,
+        };
+        arg.spender.is_default()
+            && from_default(&arg.from_subaccount)
+            && (token.metadata.operator == Some(arg.spender.owner)
+                || self.is_approved_for_all(&token.metadata.owner, &arg.spender.owner))
+    }
+
+    fn icrc37_get_token_approvals(&self, token_id: TokenIdentifier) -> Vec<TokenApproval> {
+        match self.live_token(token_id) {
+            Ok(token) => token
+                .metadata
+                .operator
+                .map(|operator| TokenApproval {
+                    token_id: Nat::from(token_id),
+                    approval_info: ApprovalInfo::of(operator),
+                })
+                .into_iter()
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    //collection approvals in spender order, after prev
+    fn icrc37_get_collection_approvals(
+        &self,
+        owner: &Account,
+        prev: Option<CollectionApproval>,
+        take: usize,
+    ) -> Vec<CollectionApproval> {
+        if !owner.is_default() {
+            return vec![];
+        }
+        let mut operators: Vec<Principal> = self
+            .operator_approvals
+            .get(&owner.owner)
+            .map(|operators| operators.iter().copied().collect())
+            .unwrap_or_default();
+        operators.sort();
+        operators
+            .into_iter()
+            .filter(|operator| {
+                prev.as_ref()
+                    .is_none_or(|prev| *operator > prev.spender.owner)
+            })
+            .take(take)
+            .map(ApprovalInfo::of)
+            .collect()
+    }
+
+    fn icrc37_transfer_from(
+        &mut self,
+        caller: Principal,
+        arg: TransferFromArg,
+        now: u64,
+    ) -> TransferResult {
+        let operation = self.deduplicate(
+            caller,
+            "icrc37_transfer_from",
+            &arg,
+            arg.memo.as_ref(),
+            arg.created_at_time,
+            now,
+        )?;
+        let token_id = token_id(&arg.token_id).ok_or(TransferError::NonExistingTokenId)?;
+        if !from_default(&arg.spender_subaccount) || !arg.from.is_default() {
+            return Err(TransferError::Unauthorized);
+        }
+        if !arg.to.is_default() || arg.to.owner == Principal::anonymous() {
+            return Err(TransferError::InvalidRecipient);
+        }
+        let tx_id = self.transfer_from(
+            caller,
+            arg.from.owner,
+            arg.to.owner,
+            token_id,
+            arg.memo,
+            now,
+        )?;
+        self.remember(operation, &tx_id);
+        Ok(tx_id)
+    }
+}
+
+//runs each argument of a batch update on its own, a failed one doesn't undo the ones before it
+fn batch<A, T, E>(
+    args: Vec<A>,
+    too_large: impl FnOnce(Nat, String) -> E,
+    apply: impl Fn(&mut State, Principal, A, u64) -> Result<T, E>,
+) -> Vec<Option<Result<T, E>>> {
+    if args.len() as u64 > MAX_UPDATE_BATCH_SIZE {
+        return vec![Some(Err(too_large(
+            Nat::from(0u64),
+            format!("at most {} updates per call", MAX_UPDATE_BATCH_SIZE),
+        )))];
+    }
+
+    let caller = api::caller();
+    STATE.with_borrow_mut(|state| {
+        args.into_iter()
+            .map(|arg| Some(apply(state, caller, arg, api::time())))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc37_metadata() -> Vec<(String, Value)> {
+    vec![(
+        String::from("icrc37:max_revoke_approvals"),
+        Value::Nat(Nat::from(MAX_UPDATE_BATCH_SIZE)),
+    )]
+}
+
+//a token has one approval slot, collections have no limit
+#[query]
+fn icrc37_max_approvals_per_token_or_collection() -> Option<Nat> {
+    None
+}
+
+#[query]
+fn icrc37_max_revoke_approvals() -> Option<Nat> {
+    Some(Nat::from(MAX_UPDATE_BATCH_SIZE))
+}
+
+#[update]
+fn icrc37_approve_tokens(args: Vec<ApproveTokenArg>) -> Vec<Option<ApproveTokenResult>> {
+    batch(
+        args,
+        |error_code, message| ApproveTokenError::GenericBatchError {
+            error_code,
+            message,
+        },
+        |state, caller, arg, now| state.icrc37_approve_token(caller, arg, now),
+    )
+}
+
+#[update]
+fn icrc37_approve_collection(
+    args: Vec<ApproveCollectionArg>,
+) -> Vec<Option<ApproveCollectionResult>> {
+    batch(
+        args,
+        |error_code, message| ApproveCollectionError::GenericBatchError {
+            error_code,
+            message,
+        },
+        |state, caller, arg, now| state.icrc37_approve_collection(caller, arg, now),
+    )
+}
+
+#[update]
+fn icrc37_revoke_token_approvals(
+    args: Vec<RevokeTokenApprovalArg>,
+) -> Vec<Option<RevokeTokenApprovalResult>> {
+    batch(
+        args,
+        |error_code, message| RevokeTokenApprovalError::GenericBatchError {
+            error_code,
+            message,
+        },
+        |state, caller, arg, now| state.icrc37_revoke_token_approval(caller, arg, now),
+    )
+}
+
+#[update]
+fn icrc37_revoke_collection_approvals(
+    args: Vec<RevokeCollectionApprovalArg>,
+) -> Vec<Option<RevokeCollectionApprovalResult>> {
+    batch(
+        args,
+        |error_code, message| RevokeCollectionApprovalError::GenericBatchError {
+            error_code,
+            message,
+        },
+        |state, caller, arg, now| state.icrc37_revoke_collection_approval(caller, arg, now),
+    )
+}
+
+#[query]
+fn icrc37_is_approved(args: Vec<IsApprovedArg>) -> Vec<bool> {
+    STATE.with_borrow(|state| {
+        args.iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|arg| state.icrc37_is_approved(arg))
+            .collect()
+    })
+}
+
+//a token has at most one approval, so a page after prev is always empty
+#[query]
+fn icrc37_get_token_approvals(
+    token_id: Nat,
+    prev: Option<TokenApproval>,
+    take: Option<Nat>,
+) -> Vec<TokenApproval> {
+    if prev.is_some() || self::take(take) == 0 {
+        return vec![];
+    }
+    STATE.with_borrow(|state| match self::token_id(&token_id) {
+        Some(token_id) => state.icrc37_get_token_approvals(token_id),
+        None => vec![],
+    })
+}
+
+#[query]
+fn icrc37_get_collection_approvals(
+    owner: Account,
+    prev: Option<CollectionApproval>,
+    take: Option<Nat>,
+) -> Vec<CollectionApproval> {
+    STATE.with_borrow(|state| state.icrc37_get_collection_approvals(&owner, prev, self::take(take)))
+}
+
+#[update]
+fn icrc37_transfer_from(args: Vec<TransferFromArg>) -> Vec<Option<TransferResult>> {
+    batch(
+        args,
+        |error_code, message| TransferError::GenericBatchError {
+            error_code,
+            message,
+        },
+        |state, caller, arg, now| state.icrc37_transfer_from(caller, arg, now),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{principal, token};
+    use std::collections::HashSet;
+
+    fn approval(spender: Principal, created_at_time: Option<u64>) -> ApprovalInfo {
+        ApprovalInfo {
+            created_at_time,
+            ..ApprovalInfo::of(spender)
+        }
+    }
+
+    #[test]
+    fn icrc37_approvals_map_onto_dip721_operators() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.tokens.insert(2, token(principal(1), 1, 2));
+        state.owners.insert(principal(1), HashSet::from([1, 2]));
+        state.verified_until.insert(principal(1), u64::MAX);
+        state.verified_until.insert(principal(3), u64::MAX);
+
+        let approve_token = |token_id: u64, spender| ApproveTokenArg {
+            token_id: Nat::from(token_id),
+            approval_info: approval(spender, None),
+        };
+        assert_eq!(
+            state.icrc37_approve_token(principal(2), approve_token(1, principal(3)), 1),
+            Err(ApproveTokenError::Unauthorized)
+        );
+        assert_eq!(
+            state.icrc37_approve_token(principal(1), approve_token(1, principal(1)), 1),
+            Err(ApproveTokenError::InvalidSpender)
+        );
+        let mut expiring = approve_token(1, principal(2));
+        expiring.approval_info.expires_at = Some(10);
+        assert!(matches!(
+            state.icrc37_approve_token(principal(1), expiring, 1),
+            Err(ApproveTokenError::GenericError { .. })
+        ));
+        assert!(state
+            .icrc37_approve_token(principal(1), approve_token(1, principal(2)), 1)
+            .is_ok());
+        assert_eq!(state.tokens[&1].metadata.operator, Some(principal(2)));
+        assert_eq!(
+            state.icrc37_get_token_approvals(1),
+            vec![TokenApproval {
+                token_id: Nat::from(1u64),
+                approval_info: ApprovalInfo::of(principal(2)),
+            }]
+        );
+
+        let is_approved = |spender, token_id: u64| IsApprovedArg {
+            spender: Account::from(spender),
+            from_subaccount: None,
+            token_id: Nat::from(token_id),
+        };
+        assert!(state.icrc37_is_approved(&is_approved(principal(2), 1)));
+        assert!(!state.icrc37_is_approved(&is_approved(principal(2), 2)));
+
+        //collection approvals cover every token of the owner
+        let approve_collection = ApproveCollectionArg {
+            approval_info: approval(principal(6), None),
+        };
+        assert!(state
+            .icrc37_approve_collection(principal(1), approve_collection, 2)
+            .is_ok());
+        assert!(state.icrc37_is_approved(&is_approved(principal(6), 2)));
+        assert_eq!(
+            state.icrc37_get_collection_approvals(&Account::from(principal(1)), None, 10),
+            vec![ApprovalInfo::of(principal(6))]
+        );
+
+        let transfer = |spender_token: u64, created_at_time| TransferFromArg {
+            spender_subaccount: None,
+            from: Account::from(principal(1)),
+            to: Account::from(principal(3)),
+            token_id: Nat::from(spender_token),
+            memo: Some(vec![7]),
+            created_at_time,
+        };
+        assert_eq!(
+            state.icrc37_transfer_from(principal(5), transfer(1, None), 3),
+            Err(TransferError::Unauthorized)
+        );
+        let tx_id = state
+            .icrc37_transfer_from(principal(2), transfer(1, Some(3)), 3)
+            .ok()
+            .unwrap();
+        assert_eq!(state.tokens[&1].metadata.owner, principal(3));
+        //the transfer clears the token approval
+        assert!(state.icrc37_get_token_approvals(1).is_empty());
+        assert_eq!(
+            state.icrc37_transfer_from(principal(2), transfer(1, Some(3)), 4),
+            Err(TransferError::Duplicate {
+                duplicate_of: tx_id
+            })
+        );
+
+        let revoke_token = RevokeTokenApprovalArg {
+            spender: None,
+            from_subaccount: None,
+            token_id: Nat::from(2u64),
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(
+            state.icrc37_revoke_token_approval(principal(1), revoke_token, 5),
+            Err(RevokeTokenApprovalError::ApprovalDoesNotExist)
+        );
+        let revoke_collection = RevokeCollectionApprovalArg {
+            spender: None,
+            from_subaccount: None,
+            memo: None,
+            created_at_time: None,
+        };
+        assert!(state
+            .icrc37_revoke_collection_approval(principal(1), revoke_collection.clone(), 5)
+            .is_ok());
+        assert!(!state.icrc37_is_approved(&is_approved(principal(6), 2)));
+        assert_eq!(
+            state.icrc37_revoke_collection_approval(principal(1), revoke_collection, 6),
+            Err(RevokeCollectionApprovalError::ApprovalDoesNotExist)
+        );
+    }
+}
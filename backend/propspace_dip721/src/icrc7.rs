@@ -0,0 +1,604 @@
+use crate::icrc3::Value;
+use crate::{NftError, State, TokenIdentifier, STATE};
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::{api, query, update};
+use serde::Deserialize;
+
+pub(crate) const MAX_QUERY_BATCH_SIZE: u64 = 100;
+pub(crate) const MAX_UPDATE_BATCH_SIZE: u64 = 20;
+pub(crate) const DEFAULT_TAKE_VALUE: u64 = 100;
+pub(crate) const MAX_TAKE_VALUE: u64 = 100;
+pub(crate) const MAX_MEMO_SIZE: usize = 32;
+//how long operations with a created_at_time are remembered for deduplication
+const TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+//how far ahead of the canister a client clock may be
+const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+
+//an operation sent with a created_at_time, identified by its caller, method and encoded arguments
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct RecentOperation {
+    caller: Principal,
+    method: String,
+    args: Vec<u8>,
+    created_at_time: u64,
+}
+
+//rejections shared by the icrc-7 and icrc-37 updates, mapped onto the error type of each method
+pub(crate) enum Rejection {
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u64 },
+    MemoTooLong,
+}
+
+//units are held by principals, so only the default subaccount ever holds tokens
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct Account {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    pub(crate) fn is_default(&self) -> bool {
+        self.subaccount
+            .as_ref()
+            .is_none_or(|s| s.iter().all(|b| *b == 0))
+    }
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    token_id: Nat,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum TransferError {
+    NonExistingTokenId,
+    InvalidRecipient,
+    Unauthorized,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+pub(crate) type TransferResult = Result<Nat, TransferError>;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct StandardRecord {
+    name: String,
+    url: String,
+}
+
+impl From<NftError> for TransferError {
+    fn from(err: NftError) -> Self {
+        match err {
+            NftError::TokenNotFound => TransferError::NonExistingTokenId,
+            NftError::SelfTransfer => TransferError::InvalidRecipient,
+            NftError::UnauthorizedOwner
+            | NftError::UnauthorizedOperator
+            | NftError::OperatorNotFound => TransferError::Unauthorized,
+            NftError::NotVerified => TransferError::GenericError {
+                error_code: Nat::from(1u64),
+                message: String::from("sender and recipient must both be verified"),
+            },
+            _ => TransferError::GenericError {
+                error_code: Nat::from(0u64),
+                message: String::from("transfer failed"),
+            },
+        }
+    }
+}
+
+impl From<Rejection> for TransferError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::TooOld => TransferError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                TransferError::CreatedInFuture { ledger_time }
+            }
+            Rejection::Duplicate { duplicate_of } => TransferError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            Rejection::MemoTooLong => memo_too_long(),
+        }
+    }
+}
+
+pub(crate) fn memo_too_long() -> TransferError {
+    TransferError::GenericError {
+        error_code: Nat::from(2u64),
+        message: format!("memo is longer than {} bytes", MAX_MEMO_SIZE),
+    }
+}
+
+pub(crate) fn token_id(id: &Nat) -> Option<TokenIdentifier> {
+    u64::try_from(&id.0).ok()
+}
+
+impl State {
+    //checks the memo and created_at_time of an update, returns the operation to remember once
+    //it has been applied, operations without a created_at_time are not deduplicated
+    pub(crate) fn deduplicate<T: CandidType>(
+        &mut self,
+        caller: Principal,
+        method: &str,
+        args: &T,
+        memo: Option<&Vec<u8>>,
+        created_at_time: Option<u64>,
+        now: u64,
+    ) -> Result<Option<RecentOperation>, Rejection> {
+        if memo.is_some_and(|memo| memo.len() > MAX_MEMO_SIZE) {
+            return Err(Rejection::MemoTooLong);
+        }
+        let created_at_time = match created_at_time {
+            Some(created_at_time) => created_at_time,
+            None => return Ok(None),
+        };
+
+        if created_at_time.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now {
+            return Err(Rejection::TooOld);
+        }
+        if created_at_time > now.saturating_add(PERMITTED_DRIFT_NANOS) {
+            return Err(Rejection::CreatedInFuture { ledger_time: now });
+        }
+
+        self.recent_operations.retain(|operation, _| {
+            operation
+                .created_at_time
+                .saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS)
+                >= now
+        });
+
+        let operation = RecentOperation {
+            caller,
+            method: method.to_owned(),
+            args: candid::encode_one(args).unwrap_or_default(),
+            created_at_time,
+        };
+        if let Some(tx_id) = self.recent_operations.get(&operation) {
+            return Err(Rejection::Duplicate {
+                duplicate_of: *tx_id,
+            });
+        }
+        Ok(Some(operation))
+    }
+
+    pub(crate) fn remember(&mut self, operation: Option<RecentOperation>, tx_id: &Nat) {
+        if let (Some(operation), Some(tx_id)) = (operation, token_id(tx_id)) {
+            self.recent_operations.insert(operation, tx_id);
+        }
+    }
+
+    fn icrc7_token_metadata(&self, token_id: TokenIdentifier) -> Option<Vec<(String, Value)>> {
+        let token = self.live_token(token_id).ok()?;
+        let mut metadata: Vec<(String, Value)> = token
+            .metadata
+            .properties
+            .iter()
+            .map(|(key, value)| (key.clone(), Value::from(value.clone())))
+            .collect();
+        metadata.push((
+            String::from("space_id"),
+            Value::Nat(Nat::from(token.metadata.space_id)),
+        ));
+        metadata.push((
+            String::from("num_units"),
+            Value::Nat(Nat::from(token.metadata.num_units)),
+        ));
+        Some(metadata)
+    }
+
+    fn icrc7_owner_of(&self, token_id: TokenIdentifier) -> Option<Account> {
+        self.live_token(token_id)
+            .ok()
+            .map(|token| Account::from(token.metadata.owner))
+    }
+
+    fn icrc7_balance_of(&self, account: &Account) -> u64 {
+        if !account.is_default() {
+            return 0;
+        }
+        self.owners
+            .get(&account.owner)
+            .map_or(0, |tokens| tokens.len() as u64)
+    }
+
+    //live token ids after prev in ascending order
+    fn icrc7_tokens(
+        &self,
+        owner: Option<&Account>,
+        prev: Option<Nat>,
+        take: Option<Nat>,
+    ) -> Vec<Nat> {
+        if owner.is_some_and(|account| !account.is_default()) {
+            return vec![];
+        }
+        let prev = prev.as_ref().and_then(token_id);
+        let take = take
+            .as_ref()
+            .and_then(token_id)
+            .unwrap_or(DEFAULT_TAKE_VALUE)
+            .min(MAX_TAKE_VALUE);
+
+        let mut token_ids: Vec<TokenIdentifier> = match owner {
+            Some(account) => self
+                .owners
+                .get(&account.owner)
+                .map(|tokens| tokens.iter().copied().collect())
+                .unwrap_or_default(),
+            None => self
+                .tokens
+                .iter()
+                .filter(|(_, token)| !token.metadata.is_burned)
+                .map(|(id, _)| *id)
+                .collect(),
+        };
+        token_ids.sort();
+        token_ids
+            .into_iter()
+            .filter(|id| prev.is_none_or(|prev| *id > prev))
+            .take(take as usize)
+            .map(Nat::from)
+            .collect()
+    }
+
+    fn icrc7_transfer(&mut self, caller: Principal, arg: TransferArg, now: u64) -> TransferResult {
+        let operation = self.deduplicate(
+            caller,
+            "icrc7_transfer",
+            &arg,
+            arg.memo.as_ref(),
+            arg.created_at_time,
+            now,
+        )?;
+        let token_id = token_id(&arg.token_id).ok_or(TransferError::NonExistingTokenId)?;
+        let from = Account {
+            owner: caller,
+            subaccount: arg.from_subaccount,
+        };
+        if !from.is_default() {
+            return Err(TransferError::Unauthorized);
+        }
+        if !arg.to.is_default() || arg.to.owner == Principal::anonymous() {
+            return Err(TransferError::InvalidRecipient);
+        }
+        //icrc-7 transfers are made by the owner, operators use the dip721 methods
+        if self.live_token(token_id)?.metadata.owner != caller {
+            return Err(TransferError::Unauthorized);
+        }
+        let tx_id = self.transfer_from(caller, caller, arg.to.owner, token_id, arg.memo, now)?;
+        self.remember(operation, &tx_id);
+        Ok(tx_id)
+    }
+}
+
+#[query]
+fn icrc7_collection_metadata() -> Vec<(String, Value)> {
+    STATE.with_borrow(|state| {
+        let metadata = &state.canister_metadata;
+        let mut entries = vec![];
+        if let Some(name) = &metadata.name {
+            entries.push((String::from("icrc7:name"), Value::Text(name.clone())));
+        }
+        if let Some(symbol) = &metadata.symbol {
+            entries.push((String::from("icrc7:symbol"), Value::Text(symbol.clone())));
+        }
+        if let Some(logo) = &metadata.logo {
+            entries.push((String::from("icrc7:logo"), Value::Text(logo.clone())));
+        }
+        entries.push((
+            String::from("icrc7:total_supply"),
+            Value::Nat(Nat::from(state.stats.total_supply)),
+        ));
+        entries.push((
+            String::from("icrc7:max_query_batch_size"),
+            Value::Nat(Nat::from(MAX_QUERY_BATCH_SIZE)),
+        ));
+        entries.push((
+            String::from("icrc7:max_update_batch_size"),
+            Value::Nat(Nat::from(MAX_UPDATE_BATCH_SIZE)),
+        ));
+        entries.push((
+            String::from("icrc7:default_take_value"),
+            Value::Nat(Nat::from(DEFAULT_TAKE_VALUE)),
+        ));
+        entries.push((
+            String::from("icrc7:max_take_value"),
+            Value::Nat(Nat::from(MAX_TAKE_VALUE)),
+        ));
+        entries.push((
+            String::from("icrc7:max_memo_size"),
+            Value::Nat(Nat::from(MAX_MEMO_SIZE)),
+        ));
+        entries.push((
+            String::from("icrc7:tx_window"),
+            Value::Nat(Nat::from(TX_WINDOW_NANOS)),
+        ));
+        entries.push((
+            String::from("icrc7:permitted_drift"),
+            Value::Nat(Nat::from(PERMITTED_DRIFT_NANOS)),
+        ));
+        entries
+    })
+}
+
+#[query]
+fn icrc7_name() -> String {
+    STATE.with_borrow(|state| state.canister_metadata.name.clone().unwrap_or_default())
+}
+
+#[query]
+fn icrc7_symbol() -> String {
+    STATE.with_borrow(|state| state.canister_metadata.symbol.clone().unwrap_or_default())
+}
+
+#[query]
+fn icrc7_description() -> Option<String> {
+    None
+}
+
+#[query]
+fn icrc7_logo() -> Option<String> {
+    STATE.with_borrow(|state| state.canister_metadata.logo.clone())
+}
+
+#[query]
+fn icrc7_total_supply() -> Nat {
+    STATE.with_borrow(|state| Nat::from(state.stats.total_supply))
+}
+
+//units are minted per space, there is no cap on the collection
+#[query]
+fn icrc7_supply_cap() -> Option<Nat> {
+    None
+}
+
+#[query]
+fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_QUERY_BATCH_SIZE))
+}
+
+#[query]
+fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(Nat::from(MAX_UPDATE_BATCH_SIZE))
+}
+
+#[query]
+fn icrc7_default_take_value() -> Option<Nat> {
+    Some(Nat::from(DEFAULT_TAKE_VALUE))
+}
+
+#[query]
+fn icrc7_max_take_value() -> Option<Nat> {
+    Some(Nat::from(MAX_TAKE_VALUE))
+}
+
+#[query]
+fn icrc7_max_memo_size() -> Option<Nat> {
+    Some(Nat::from(MAX_MEMO_SIZE))
+}
+
+#[query]
+fn icrc7_tx_window() -> Option<Nat> {
+    Some(Nat::from(TX_WINDOW_NANOS))
+}
+
+#[query]
+fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(Nat::from(PERMITTED_DRIFT_NANOS))
+}
+
+#[query]
+fn icrc7_atomic_batch_transfers() -> Option<bool> {
+    Some(false)
+}
+
+#[query]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, Value)>>> {
+    STATE.with_borrow(|state| {
+        token_ids
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|id| token_id(id).and_then(|id| state.icrc7_token_metadata(id)))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    STATE.with_borrow(|state| {
+        token_ids
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|id| token_id(id).and_then(|id| state.icrc7_owner_of(id)))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    STATE.with_borrow(|state| {
+        accounts
+            .iter()
+            .take(MAX_QUERY_BATCH_SIZE as usize)
+            .map(|account| Nat::from(state.icrc7_balance_of(account)))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    STATE.with_borrow(|state| state.icrc7_tokens(None, prev, take))
+}
+
+#[query]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    STATE.with_borrow(|state| state.icrc7_tokens(Some(&account), prev, take))
+}
+
+//transfers are applied one by one, a failed transfer doesn't undo the ones before it
+#[update]
+fn icrc7_transfer(args: Vec<TransferArg>) -> Vec<Option<TransferResult>> {
+    if args.len() as u64 > MAX_UPDATE_BATCH_SIZE {
+        return vec![Some(Err(TransferError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message: format!("at most {} transfers per call", MAX_UPDATE_BATCH_SIZE),
+        }))];
+    }
+
+    let caller = api::caller();
+    STATE.with_borrow_mut(|state| {
+        args.into_iter()
+            .map(|arg| Some(state.icrc7_transfer(caller, arg, api::time())))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    ["ICRC-7", "ICRC-37", "ICRC-3", "ICRC-10"]
+        .into_iter()
+        .map(|name| StandardRecord {
+            name: name.to_owned(),
+            url: format!("https://github.com/dfinity/ICRC/tree/main/ICRCs/{}", name),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{principal, token};
+    use crate::GenericValue;
+    use std::collections::HashSet;
+
+    #[test]
+    fn icrc7_views_and_transfers_tokens() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.tokens.insert(2, token(principal(1), 1, 2));
+        state.tokens.insert(3, token(principal(2), 1, 2));
+        state.owners.insert(principal(1), HashSet::from([1, 2]));
+        state.owners.insert(principal(2), HashSet::from([3]));
+        state.verified_until.insert(principal(1), 10);
+        state.verified_until.insert(principal(2), 10);
+
+        let metadata = state.icrc7_token_metadata(1).unwrap();
+        assert_eq!(
+            metadata[0],
+            (String::from("rooms"), Value::Nat(Nat::from(4u64)))
+        );
+        assert!(metadata.contains(&(String::from("num_units"), Value::Nat(Nat::from(4u64)))));
+        assert_eq!(
+            Value::from(GenericValue::NestedContent(vec![(
+                String::from("pool"),
+                GenericValue::BoolContent(true)
+            )])),
+            Value::Map(vec![(String::from("pool"), Value::Nat(Nat::from(1u64)))])
+        );
+
+        let owner = Account::from(principal(1));
+        assert_eq!(state.icrc7_balance_of(&owner), 2);
+        assert_eq!(
+            state.icrc7_tokens(Some(&owner), None, None),
+            vec![Nat::from(1u64), Nat::from(2u64)]
+        );
+        assert_eq!(
+            state.icrc7_tokens(None, Some(Nat::from(1u64)), Some(Nat::from(1u64))),
+            vec![Nat::from(2u64)]
+        );
+
+        let transfer = |to: Account, token_id: u64| TransferArg {
+            from_subaccount: None,
+            to,
+            token_id: Nat::from(token_id),
+            memo: None,
+            created_at_time: None,
+        };
+        assert_eq!(
+            state.icrc7_transfer(principal(2), transfer(owner.clone(), 1), 1),
+            Err(TransferError::Unauthorized)
+        );
+        assert_eq!(
+            state.icrc7_transfer(principal(1), transfer(owner.clone(), 9), 1),
+            Err(TransferError::NonExistingTokenId)
+        );
+        let other_subaccount = Account {
+            owner: principal(2),
+            subaccount: Some(vec![1; 32]),
+        };
+        assert_eq!(
+            state.icrc7_transfer(principal(1), transfer(other_subaccount, 1), 1),
+            Err(TransferError::InvalidRecipient)
+        );
+        assert!(state
+            .icrc7_transfer(principal(1), transfer(Account::from(principal(2)), 1), 1)
+            .is_ok());
+        assert_eq!(state.icrc7_owner_of(1), Some(Account::from(principal(2))));
+        assert_eq!(state.icrc7_balance_of(&owner), 1);
+    }
+
+    #[test]
+    fn icrc7_transfers_are_deduplicated_within_the_window() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.owners.insert(principal(1), HashSet::from([1]));
+        state.verified_until.insert(principal(1), u64::MAX);
+        state.verified_until.insert(principal(2), u64::MAX);
+
+        let now = TX_WINDOW_NANOS * 2;
+        let mut arg = TransferArg {
+            from_subaccount: None,
+            to: Account::from(principal(2)),
+            token_id: Nat::from(1u64),
+            memo: Some(vec![0; MAX_MEMO_SIZE + 1]),
+            created_at_time: Some(now),
+        };
+        assert!(matches!(
+            state.icrc7_transfer(principal(1), arg.clone(), now),
+            Err(TransferError::GenericError { .. })
+        ));
+        arg.memo = Some(vec![1]);
+        arg.created_at_time = Some(now - TX_WINDOW_NANOS - PERMITTED_DRIFT_NANOS - 1);
+        assert_eq!(
+            state.icrc7_transfer(principal(1), arg.clone(), now),
+            Err(TransferError::TooOld)
+        );
+        arg.created_at_time = Some(now + PERMITTED_DRIFT_NANOS + 1);
+        assert_eq!(
+            state.icrc7_transfer(principal(1), arg.clone(), now),
+            Err(TransferError::CreatedInFuture { ledger_time: now })
+        );
+
+        arg.created_at_time = Some(now);
+        let tx_id = state
+            .icrc7_transfer(principal(1), arg.clone(), now)
+            .ok()
+            .unwrap();
+        assert_eq!(
+            state.icrc7_transfer(principal(1), arg.clone(), now + 1),
+            Err(TransferError::Duplicate {
+                duplicate_of: tx_id.clone()
+            })
+        );
+        //the memo is kept with the transfer in the transaction log
+        let details = &state.transactions[0].details;
+        assert!(details.iter().any(|(key, value)| key == "memo"
+            && matches!(value, GenericValue::BlobContent(memo) if *memo == vec![1])));
+    }
+}
@@ -9,6 +9,21 @@ use candid::{CandidType, Int, Nat, Principal};
 use ic_cdk::{api, query, update};
 use serde::{Deserialize, Serialize};
 
+mod icrc3;
+mod icrc37;
+mod icrc7;
+
+//the candid export names these at the crate root
+use icrc3::{
+    ArchiveInfo, BlockType, DataCertificate, GetArchivesArgs, GetBlocksArgs, GetBlocksResult, Value,
+};
+use icrc37::{
+    ApproveCollectionArg, ApproveCollectionResult, ApproveTokenArg, ApproveTokenResult,
+    CollectionApproval, IsApprovedArg, RevokeCollectionApprovalArg, RevokeCollectionApprovalResult,
+    RevokeTokenApprovalArg, RevokeTokenApprovalResult, TokenApproval, TransferFromArg,
+};
+use icrc7::{Account, RecentOperation, StandardRecord, TransferArg, TransferResult};
+
 #[derive(CandidType, Serialize)]
 enum CanisterError {
     NotCustodian,
@@ -36,6 +51,7 @@ enum NftError {
     UnitsNotAvailable,
     InsufficientUnits,
     SenderNotOwner,
+    NotVerified,
     Other(String),
 }
 
@@ -112,6 +128,12 @@ struct TokenMetaData {
     minted_by: Principal,
     space_id: u64,
     num_units: u64,
+    //principal allowed to transfer this token on the owner's behalf
+    operator: Option<Principal>,
+    approved_at: Option<u64>,
+    approved_by: Option<Principal>,
+    transferred_at: Option<u64>,
+    transferred_by: Option<Principal>,
 }
 
 impl TokenMetaData {
@@ -120,6 +142,7 @@ impl TokenMetaData {
         properties: Option<Vec<(String, GenericValue)>>,
         num_units: u64,
         space_id: u64,
+        minted_at: u64,
     ) -> Self {
         Self {
             owner,
@@ -128,9 +151,14 @@ impl TokenMetaData {
             properties: properties.unwrap_or_default(),
             burned_at: None,
             burned_by: None,
-            minted_at: api::time(),
+            minted_at,
             minted_by: owner,
             space_id,
+            operator: None,
+            approved_at: None,
+            approved_by: None,
+            transferred_at: None,
+            transferred_by: None,
         }
     }
 }
@@ -143,6 +171,15 @@ struct InitArguments {
     custoidians: Option<HashSet<Principal>>,
 }
 
+//what a mint creates, the caller and time are passed alongside
+struct MintArgs {
+    owner: Principal,
+    properties: Option<Vec<(String, GenericValue)>>,
+    space_id: u64,
+    token_data: TokenData,
+    num_units: u64,
+}
+
 #[derive(CandidType, Deserialize, Default, Copy, Clone)]
 struct Space {
     id: u64,
@@ -150,6 +187,66 @@ struct Space {
     num_units_available: u64,
 }
 
+//dip721 v2 view of the canister metadata
+#[derive(CandidType, Deserialize, Clone)]
+struct Metadata {
+    logo: Option<String>,
+    name: Option<String>,
+    created_at: u64,
+    upgraded_at: u64,
+    custodians: Vec<Principal>,
+    symbol: Option<String>,
+}
+
+//dip721 v2 view of the stats
+#[derive(CandidType, Deserialize, Clone)]
+struct DipStats {
+    cycles: Nat,
+    total_transactions: Nat,
+    total_unique_holders: Nat,
+    total_supply: Nat,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+enum SupportedInterface {
+    Approval,
+    Mint,
+    Burn,
+    TransactionHistory,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct TxEvent {
+    time: u64,
+    caller: Principal,
+    operation: String,
+    details: Vec<(String, GenericValue)>,
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+struct TxEntry {
+    tx_id: u64,
+    event: TxEvent,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TxHistoryPage {
+    //oldest first, so offsets stay valid as new transactions are appended
+    transactions: Vec<TxEntry>,
+    //number of transactions in the history across all pages
+    total: u64,
+}
+
+const MAX_PAGE_SIZE: u64 = 100;
+
+fn principal_detail(key: &str, principal: Principal) -> (String, GenericValue) {
+    (key.to_owned(), GenericValue::Principal(principal))
+}
+
+fn nat_detail(key: &str, value: u64) -> (String, GenericValue) {
+    (key.to_owned(), GenericValue::NatContent(Nat::from(value)))
+}
+
 #[derive(CandidType, Deserialize, Default)]
 struct State {
     canister_metadata: CanisterMetaData,
@@ -157,11 +254,579 @@ struct State {
     owners: HashMap<Principal, HashSet<TokenIdentifier>>,
     spaces: HashMap<u64, Space>,
     stats: Stats,
+    //kyc expiry of each principal allowed to trade, kept in step by the dao backend
+    verified_until: HashMap<Principal, u64>,
+    //operators approved for every token of an owner
+    operator_approvals: HashMap<Principal, HashSet<Principal>>,
+    //indexed by transaction id, never rewritten
+    transactions: Vec<TxEvent>,
+    //ids of the transactions touching each token and principal, in order
+    token_transactions: HashMap<TokenIdentifier, Vec<u64>>,
+    principal_transactions: HashMap<Principal, Vec<u64>>,
+    //icrc-3 hash of the block built from each transaction, each one covering its parent
+    block_hashes: Vec<Vec<u8>>,
+    //operations sent with a created_at_time, with the transaction they were recorded as
+    recent_operations: HashMap<RecentOperation, u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+struct SpaceSupply {
+    space_id: u64,
+    //units held in live tokens
+    units_minted: u64,
+    //units left to mint
+    units_available: u64,
+}
+
+//a live token's share of a space
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+struct SpaceHolding {
+    token_id: TokenIdentifier,
+    owner: Principal,
+    num_units: u64,
+}
+
+//discrepancies between the owners index and the tokens map
+#[derive(CandidType, Deserialize, Default)]
+struct OwnerIndexReport {
+    //live tokens that are not listed under their owner
+    missing: Vec<(Principal, TokenIdentifier)>,
+    //entries in the index pointing at burned tokens or tokens held by someone else
+    stale: Vec<(Principal, TokenIdentifier)>,
+}
+
+//standard methods take nat ids, every id handed out here fits in a u64
+fn to_u64(id: &Nat, missing: NftError) -> NftResult<u64> {
+    u64::try_from(&id.0).map_err(|_| missing)
 }
 
 type CanisterResult<T = ()> = Result<T, String>;
 type NftResult<T = ()> = Result<T, NftError>;
 
+impl State {
+    //tokens are never removed from the map, so its length is the last identifier handed out
+    fn next_token_id(&self) -> TokenIdentifier {
+        self.tokens.len() as u64 + 1
+    }
+
+    fn is_verified(&self, principal: &Principal, now: u64) -> bool {
+        self.verified_until
+            .get(principal)
+            .is_some_and(|until| *until > now)
+    }
+
+    //appends to the transaction log and returns the new transaction id
+    //the transaction is listed in the history of the given tokens, the caller and every principal in the details
+    fn record(
+        &mut self,
+        caller: Principal,
+        operation: &str,
+        details: Vec<(String, GenericValue)>,
+        token_ids: &[TokenIdentifier],
+        now: u64,
+    ) -> Nat {
+        let tx_id = self.transactions.len() as u64;
+
+        let mut principals = vec![caller];
+        for (_, value) in details.iter() {
+            if let GenericValue::Principal(principal) = value {
+                if !principals.contains(principal) {
+                    principals.push(*principal);
+                }
+            }
+        }
+        for principal in principals {
+            self.principal_transactions
+                .entry(principal)
+                .or_default()
+                .push(tx_id);
+        }
+        for token_id in token_ids {
+            let history = self.token_transactions.entry(*token_id).or_default();
+            if history.last() != Some(&tx_id) {
+                history.push(tx_id);
+            }
+        }
+
+        self.transactions.push(TxEvent {
+            time: now,
+            caller,
+            operation: operation.to_owned(),
+            details,
+        });
+        self.append_block();
+        self.stats.total_transactions += 1;
+        Nat::from(tx_id)
+    }
+
+    fn history_page(&self, tx_ids: &[u64], offset: u64, limit: u64) -> TxHistoryPage {
+        TxHistoryPage {
+            transactions: tx_ids
+                .iter()
+                .skip(offset as usize)
+                .take(limit.min(MAX_PAGE_SIZE) as usize)
+                .map(|tx_id| TxEntry {
+                    tx_id: *tx_id,
+                    event: self.transactions[*tx_id as usize].clone(),
+                })
+                .collect(),
+            total: tx_ids.len() as u64,
+        }
+    }
+
+    fn token_history(&self, token_id: TokenIdentifier, offset: u64, limit: u64) -> TxHistoryPage {
+        let tx_ids = self
+            .token_transactions
+            .get(&token_id)
+            .map(|ids| ids.as_slice())
+            .unwrap_or_default();
+        self.history_page(tx_ids, offset, limit)
+    }
+
+    fn principal_history(&self, principal: &Principal, offset: u64, limit: u64) -> TxHistoryPage {
+        let tx_ids = self
+            .principal_transactions
+            .get(principal)
+            .map(|ids| ids.as_slice())
+            .unwrap_or_default();
+        self.history_page(tx_ids, offset, limit)
+    }
+
+    fn live_token(&self, token_id: TokenIdentifier) -> NftResult<&Token> {
+        match self.tokens.get(&token_id) {
+            Some(token) if !token.metadata.is_burned => Ok(token),
+            _ => Err(NftError::TokenNotFound),
+        }
+    }
+
+    fn is_approved_for_all(&self, owner: &Principal, operator: &Principal) -> bool {
+        self.operator_approvals
+            .get(owner)
+            .is_some_and(|operators| operators.contains(operator))
+    }
+
+    //the owner of a token, its operator and the owner's operators may move it
+    fn check_operator(&self, caller: &Principal, token: &Token) -> NftResult {
+        let owner = &token.metadata.owner;
+        if caller == owner
+            || token.metadata.operator.as_ref() == Some(caller)
+            || self.is_approved_for_all(owner, caller)
+        {
+            return Ok(());
+        }
+        if token.metadata.operator.is_none() && !self.operator_approvals.contains_key(owner) {
+            return Err(NftError::OperatorNotFound);
+        }
+        Err(NftError::UnauthorizedOperator)
+    }
+
+    fn approve(
+        &mut self,
+        caller: Principal,
+        operator: Principal,
+        token_id: TokenIdentifier,
+        memo: Option<Vec<u8>>,
+        now: u64,
+    ) -> NftResult<Nat> {
+        if operator == caller {
+            return Err(NftError::SelfApprove);
+        }
+        if self.live_token(token_id)?.metadata.owner != caller {
+            return Err(NftError::UnauthorizedOwner);
+        }
+
+        if let Some(token) = self.tokens.get_mut(&token_id) {
+            token.metadata.operator = Some(operator);
+            token.metadata.approved_at = Some(now);
+            token.metadata.approved_by = Some(caller);
+        }
+        let mut details = vec![
+            principal_detail("operator", operator),
+            nat_detail("token_identifier", token_id),
+        ];
+        if let Some(memo) = memo {
+            details.push((String::from("memo"), GenericValue::BlobContent(memo)));
+        }
+        Ok(self.record(caller, "approve", details, &[token_id], now))
+    }
+
+    fn set_approval_for_all(
+        &mut self,
+        caller: Principal,
+        operator: Principal,
+        is_approved: bool,
+        memo: Option<Vec<u8>>,
+        now: u64,
+    ) -> NftResult<Nat> {
+        if operator == caller {
+            return Err(NftError::SelfApprove);
+        }
+
+        if is_approved {
+            self.operator_approvals
+                .entry(caller)
+                .or_default()
+                .insert(operator);
+        } else {
+            let operators = self.operator_approvals.entry(caller).or_default();
+            let removed = operators.remove(&operator);
+            if operators.is_empty() {
+                self.operator_approvals.remove(&caller);
+            }
+            if !removed {
+                return Err(NftError::OperatorNotFound);
+            }
+        }
+        let mut details = vec![
+            principal_detail("operator", operator),
+            (
+                String::from("is_approved"),
+                GenericValue::BoolContent(is_approved),
+            ),
+        ];
+        if let Some(memo) = memo {
+            details.push((String::from("memo"), GenericValue::BlobContent(memo)));
+        }
+        Ok(self.record(caller, "setApprovalForAll", details, &[], now))
+    }
+
+    //moves the whole token, the caller must be the owner or one of its operators
+    //the memo is kept in the transaction log
+    fn transfer_from(
+        &mut self,
+        caller: Principal,
+        from: Principal,
+        to: Principal,
+        token_id: TokenIdentifier,
+        memo: Option<Vec<u8>>,
+        now: u64,
+    ) -> NftResult<Nat> {
+        if from == to {
+            return Err(NftError::SelfTransfer);
+        }
+        let token = self.live_token(token_id)?;
+        if token.metadata.owner != from {
+            return Err(NftError::UnauthorizedOwner);
+        }
+        self.check_operator(&caller, token)?;
+        if !self.is_verified(&from, now) || !self.is_verified(&to, now) {
+            return Err(NftError::NotVerified);
+        }
+
+        self.remove_owner_token(from, token_id);
+        self.add_owner_token(to, token_id);
+        if let Some(token) = self.tokens.get_mut(&token_id) {
+            token.metadata.owner = to;
+            token.metadata.operator = None;
+            token.metadata.transferred_at = Some(now);
+            token.metadata.transferred_by = Some(caller);
+        }
+
+        let operation = if caller == from {
+            "transfer"
+        } else {
+            "transferFrom"
+        };
+        let mut details = vec![
+            principal_detail("from", from),
+            principal_detail("to", to),
+            nat_detail("token_identifier", token_id),
+        ];
+        if let Some(memo) = memo {
+            details.push((String::from("memo"), GenericValue::BlobContent(memo)));
+        }
+        Ok(self.record(caller, operation, details, &[token_id], now))
+    }
+
+    //ids are assigned here, whatever id the caller passed is ignored
+    fn create_space(&mut self, mut space: Space, caller: Principal, now: u64) -> Space {
+        space.id = self.stats.total_spaces + 1;
+        self.spaces.insert(space.id, space);
+        self.stats.total_spaces += 1;
+        self.record(
+            caller,
+            "createSpace",
+            vec![
+                nat_detail("space_id", space.id),
+                nat_detail("price_per_unit", space.price_per_unit),
+                nat_detail("num_units", space.num_units_available),
+            ],
+            &[],
+            now,
+        );
+        space
+    }
+
+    fn add_owner_token(&mut self, owner: Principal, token_id: TokenIdentifier) {
+        self.owners.entry(owner).or_default().insert(token_id);
+        self.stats.total_unique_holders = self.owners.len() as u64;
+    }
+
+    fn remove_owner_token(&mut self, owner: Principal, token_id: TokenIdentifier) {
+        if let Some(tokens) = self.owners.get_mut(&owner) {
+            tokens.remove(&token_id);
+            if tokens.is_empty() {
+                self.owners.remove(&owner);
+            }
+        }
+        self.stats.total_unique_holders = self.owners.len() as u64;
+    }
+
+    fn mint(&mut self, args: MintArgs, caller: Principal, now: u64) -> NftResult<TokenIdentifier> {
+        let MintArgs {
+            owner,
+            properties,
+            space_id,
+            token_data,
+            num_units,
+        } = args;
+        let token_id = self.next_token_id();
+        if self.tokens.contains_key(&token_id) {
+            return Err(NftError::ExistedNFT);
+        }
+        if num_units == 0 {
+            return Err(NftError::Other(String::from(
+                "num_units must be greater than zero",
+            )));
+        }
+
+        match self.spaces.get_mut(&space_id) {
+            Some(space) => {
+                if num_units > space.num_units_available {
+                    return Err(NftError::UnitsNotAvailable);
+                }
+                space.num_units_available -= num_units;
+            }
+            None => {
+                return Err(NftError::Other(String::from("house does not exist")));
+            }
+        }
+
+        let token = Token {
+            metadata: TokenMetaData::new(owner, properties, num_units, space_id, now),
+            data: token_data,
+        };
+        self.tokens.insert(token_id, token);
+        self.stats.total_supply += 1;
+        self.add_owner_token(owner, token_id);
+        self.record(
+            caller,
+            "mint",
+            vec![
+                principal_detail("to", owner),
+                nat_detail("token_identifier", token_id),
+                nat_detail("space_id", space_id),
+                nat_detail("num_units", num_units),
+            ],
+            &[token_id],
+            now,
+        );
+        Ok(token_id)
+    }
+
+    fn burn(&mut self, token_id: TokenIdentifier, burned_by: Principal, now: u64) -> NftResult {
+        let owner = match self.tokens.get(&token_id) {
+            Some(token) if !token.metadata.is_burned => token.metadata.owner,
+            _ => return Err(NftError::TokenNotFound),
+        };
+        self.remove_owner_token(owner, token_id);
+
+        if let Some(token) = self.tokens.get_mut(&token_id) {
+            //burned units go back to the space they were minted from
+            if let Some(space) = self.spaces.get_mut(&token.metadata.space_id) {
+                space.num_units_available += token.metadata.num_units;
+            }
+            token.metadata.owner = Principal::anonymous();
+            token.metadata.operator = None;
+            token.metadata.is_burned = true;
+            token.metadata.burned_at = Some(now);
+            token.metadata.burned_by = Some(burned_by);
+            token.metadata.minted_by = Principal::anonymous();
+            self.stats.total_supply -= 1;
+            let returned_units = token.metadata.num_units;
+            self.record(
+                burned_by,
+                "burn",
+                vec![
+                    principal_detail("from", owner),
+                    nat_detail("token_identifier", token_id),
+                    nat_detail("num_units", returned_units),
+                ],
+                &[token_id],
+                now,
+            );
+            Ok(())
+        } else {
+            Err(NftError::TokenNotFound)
+        }
+    }
+
+    //moves units out of a token into the receiver's token for the same space, minting one if needed
+    //a token left with no units is burned
+    fn trade_units(
+        &mut self,
+        token_id: TokenIdentifier,
+        sender: Principal,
+        receiver: Principal,
+        num_units: u64,
+        caller: Principal,
+        now: u64,
+    ) -> NftResult<TokenIdentifier> {
+        if sender == receiver {
+            return Err(NftError::SelfTransfer);
+        }
+        if !self.is_verified(&sender, now) || !self.is_verified(&receiver, now) {
+            return Err(NftError::NotVerified);
+        }
+
+        let token = match self.tokens.get(&token_id) {
+            Some(token) if !token.metadata.is_burned => token.clone(),
+            _ => return Err(NftError::TokenNotFound),
+        };
+        if sender != token.metadata.owner {
+            return Err(NftError::UnauthorizedOwner);
+        }
+        //only the owner or an operator it approved moves its units, custodians included
+        self.check_operator(&caller, &token)?;
+        if num_units == 0 || token.metadata.num_units < num_units {
+            return Err(NftError::InsufficientUnits);
+        }
+
+        let space_id = token.metadata.space_id;
+        let receiver_token_id = self.owners.get(&receiver).and_then(|ids| {
+            ids.iter().copied().find(|id| {
+                self.tokens
+                    .get(id)
+                    .is_some_and(|t| !t.metadata.is_burned && t.metadata.space_id == space_id)
+            })
+        });
+
+        //credit receiver
+        let receiver_token_id = match receiver_token_id {
+            Some(id) => {
+                self.tokens
+                    .entry(id)
+                    .and_modify(|e| e.metadata.num_units += num_units);
+                id
+            }
+            None => {
+                let id = self.next_token_id();
+                let metadata = TokenMetaData::new(
+                    receiver,
+                    Some(token.metadata.properties.clone()),
+                    num_units,
+                    space_id,
+                    now,
+                );
+                self.tokens.insert(
+                    id,
+                    Token {
+                        metadata,
+                        data: token.data.clone(),
+                    },
+                );
+                self.stats.total_supply += 1;
+                self.add_owner_token(receiver, id);
+                id
+            }
+        };
+
+        //debit sender
+        self.tokens
+            .entry(token_id)
+            .and_modify(|e| e.metadata.num_units -= num_units);
+        self.record(
+            caller,
+            "tradeUnits",
+            vec![
+                principal_detail("from", sender),
+                principal_detail("to", receiver),
+                nat_detail("token_identifier", token_id),
+                nat_detail("to_token_identifier", receiver_token_id),
+                nat_detail("num_units", num_units),
+            ],
+            &[token_id, receiver_token_id],
+            now,
+        );
+        if token.metadata.num_units == num_units {
+            self.burn(token_id, caller, now)?;
+        }
+        Ok(receiver_token_id)
+    }
+
+    fn space_supply(&self, space_id: u64) -> NftResult<SpaceSupply> {
+        let space = match self.spaces.get(&space_id) {
+            Some(space) => space,
+            None => return Err(NftError::Other(String::from("space not found"))),
+        };
+
+        let units_minted = self
+            .tokens
+            .values()
+            .filter(|t| !t.metadata.is_burned && t.metadata.space_id == space_id)
+            .map(|t| t.metadata.num_units)
+            .sum();
+
+        Ok(SpaceSupply {
+            space_id,
+            units_minted,
+            units_available: space.num_units_available,
+        })
+    }
+
+    //sorted by token id so callers splitting by units get the same order every time
+    fn space_holdings(&self, space_id: u64) -> NftResult<Vec<SpaceHolding>> {
+        if !self.spaces.contains_key(&space_id) {
+            return Err(NftError::Other(String::from("space not found")));
+        }
+
+        let mut holdings: Vec<SpaceHolding> = self
+            .tokens
+            .iter()
+            .filter(|(_, t)| !t.metadata.is_burned && t.metadata.space_id == space_id)
+            .map(|(token_id, t)| SpaceHolding {
+                token_id: *token_id,
+                owner: t.metadata.owner,
+                num_units: t.metadata.num_units,
+            })
+            .collect();
+        holdings.sort_by_key(|h| h.token_id);
+        Ok(holdings)
+    }
+
+    fn check_owner_index(&self) -> OwnerIndexReport {
+        let mut report = OwnerIndexReport::default();
+        for (token_id, token) in self.tokens.iter() {
+            if token.metadata.is_burned {
+                continue;
+            }
+            let indexed = self
+                .owners
+                .get(&token.metadata.owner)
+                .is_some_and(|ids| ids.contains(token_id));
+            if !indexed {
+                report.missing.push((token.metadata.owner, *token_id));
+            }
+        }
+
+        for (owner, ids) in self.owners.iter() {
+            for token_id in ids {
+                let owned = self
+                    .tokens
+                    .get(token_id)
+                    .is_some_and(|t| !t.metadata.is_burned && t.metadata.owner == *owner);
+                if !owned {
+                    report.stale.push((*owner, *token_id));
+                }
+            }
+        }
+
+        report.missing.sort();
+        report.stale.sort();
+        report
+    }
+}
+
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::default());
 }
@@ -184,6 +849,33 @@ fn init(args: Option<InitArguments>) {
     });
 }
 
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    STATE.with_borrow(|state| {
+        ic_cdk::storage::stable_save((state,)).expect("failed to save state to stable memory")
+    });
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    //releases before the state was persisted leave stable memory empty, the upgrader then
+    //becomes the custodian as on a fresh install
+    let mut state = if api::stable::stable64_size() == 0 {
+        let mut state = State::default();
+        state.canister_metadata.custodians = HashSet::from([api::caller()]);
+        state.canister_metadata.created_at = api::time();
+        state
+    } else {
+        let (state,): (State,) =
+            ic_cdk::storage::stable_restore().expect("failed to restore state from stable memory");
+        state
+    };
+    state.canister_metadata.upgraded_at = api::time();
+    //certified data does not survive an upgrade, rebuilding the hashes certifies the tip again
+    state.rebuild_block_hashes();
+    STATE.set(state);
+}
+
 fn is_custodian() -> CanisterResult {
     STATE.with_borrow(|state| {
         state
@@ -301,72 +993,48 @@ fn mint_token(
     num_units: u64,
 ) -> NftResult<TokenIdentifier> {
     STATE.with_borrow_mut(|state| {
-        let token_id = state.stats.total_supply + 1;
-        if state.tokens.contains_key(&token_id) {
-            return Err(NftError::ExistedNFT);
-        }
-        let token_metadata = TokenMetaData::new(owner, properties, num_units, space_id);
+        state.mint(
+            MintArgs {
+                owner,
+                properties,
+                space_id,
+                token_data,
+                num_units,
+            },
+            api::caller(),
+            api::time(),
+        )
+    })
+}
 
-        let token = Token {
-            metadata: token_metadata,
-            data: token_data,
+#[update(name = "setVerifiedUntil", guard = "is_custodian")]
+fn set_verified_until(principal: Principal, verified_until: Option<u64>) -> NftResult {
+    STATE.with_borrow_mut(|state| {
+        match verified_until {
+            Some(until) => state.verified_until.insert(principal, until),
+            None => state.verified_until.remove(&principal),
         };
-
-        let space = state.spaces.get(&space_id);
-        match (space) {
-            Some(space) => {
-                if (space.num_units_available > 0) {
-                    state.tokens.insert(token_id, token);
-                    state.stats.total_supply += 1;
-                    let saved_owner = state.owners.get(&owner);
-                    match (saved_owner) {
-                        Some(_) => {}
-                        None => {
-                            //random state for owner bound to first ever token?
-                            state.owners.insert(owner, HashSet::from([token_id]));
-                        }
-                    }
-                } else {
-                    return Err(NftError::UnitsNotAvailable);
-                }
-            }
-            None => {
-                return Err(NftError::Other(String::from("house does not exist")));
-            }
-        }
-        Ok(token_id)
+        Ok(())
     })
 }
 
-#[update(name = "tradeUnits", guard = "is_custodian")]
+//the caller must be the sender or one of its operators
+#[update(name = "tradeUnits")]
 fn trade_units(
-    //empty string if optional "" add struct later
     token_id: TokenIdentifier,
     sender: Principal,
-    receiever: Principal,
+    receiver: Principal,
     num_units: u64,
-) -> NftResult {
+) -> NftResult<TokenIdentifier> {
     STATE.with_borrow_mut(|state| {
-        if let Some(token) = state.tokens.get_mut(&token_id) {
-            if sender == token.metadata.owner {
-                if (token.metadata.num_units >= num_units) {
-                    //debit sender
-                    state
-                        .tokens
-                        .entry(token_id)
-                        .and_modify(|e| e.metadata.num_units -= num_units);
-
-                    //fix transfer to reciever later
-                    Ok(())
-                } else {
-                    Err(NftError::InsufficientUnits)
-                }
-            } else {
-                Err(NftError::UnauthorizedOwner)
-            }
-        } else {
-            Err(NftError::TokenNotFound)
-        }
+        state.trade_units(
+            token_id,
+            sender,
+            receiver,
+            num_units,
+            api::caller(),
+            api::time(),
+        )
     })
 }
 
@@ -396,15 +1064,38 @@ fn get_space_data(space_id: u64) -> NftResult<Space> {
     })
 }
 
+#[query(name = "getSpaceSupply")]
+fn get_space_supply(space_id: u64) -> NftResult<SpaceSupply> {
+    STATE.with_borrow(|state| state.space_supply(space_id))
+}
+
+#[query(name = "getSpaceHoldings")]
+fn get_space_holdings(space_id: u64) -> NftResult<Vec<SpaceHolding>> {
+    STATE.with_borrow(|state| state.space_holdings(space_id))
+}
+
 #[update(name = "createSpace", guard = "is_custodian")]
 fn create_space(space: Space) -> NftResult<Space> {
+    STATE.with_borrow_mut(|state| Ok(state.create_space(space, api::caller(), api::time())))
+}
+
+#[update(name = "setSpacePrice", guard = "is_custodian")]
+fn set_space_price(space_id: u64, price_per_unit: u64) -> NftResult {
     STATE.with_borrow_mut(|state| {
-        state.spaces.insert(state.stats.total_spaces + 1, space);
-        state.stats.total_spaces += 1;
-        Ok(space)
+        if let Some(space) = state.spaces.get_mut(&space_id) {
+            space.price_per_unit = price_per_unit;
+            Ok(())
+        } else {
+            Err(NftError::Other(String::from("space not found")))
+        }
     })
 }
 
+#[query(name = "checkOwnerIndex", guard = "is_custodian")]
+fn check_owner_index() -> OwnerIndexReport {
+    STATE.with_borrow(|state| state.check_owner_index())
+}
+
 #[query(name = "getAllUserTokens")]
 fn get_all_user_tokens(user: Principal) -> Vec<Token> {
     STATE.with(|state| {
@@ -419,19 +1110,411 @@ fn get_all_user_tokens(user: Principal) -> Vec<Token> {
 }
 #[update(name = "burnToken", guard = "is_custodian")]
 fn burn_token(token_id: TokenIdentifier) -> NftResult {
+    STATE.with_borrow_mut(|state| state.burn(token_id, api::caller(), api::time()))
+}
+
+#[update(name = "approve")]
+fn approve(operator: Principal, token_id: Nat) -> NftResult<Nat> {
+    let token_id = to_u64(&token_id, NftError::TokenNotFound)?;
     STATE.with_borrow_mut(|state| {
-        if let Some(token) = state.tokens.get_mut(&token_id) {
-            token.metadata.owner = Principal::anonymous();
-            token.metadata.is_burned = true;
-            token.metadata.burned_at = Some(api::time());
-            token.metadata.burned_by = Some(api::caller());
-            token.metadata.minted_by = Principal::anonymous();
-            state.stats.total_supply -= 1;
-            Ok(())
-        } else {
-            Err(NftError::TokenNotFound)
+        state.approve(api::caller(), operator, token_id, None, api::time())
+    })
+}
+
+#[update(name = "setApprovalForAll")]
+fn set_approval_for_all(operator: Principal, is_approved: bool) -> NftResult<Nat> {
+    STATE.with_borrow_mut(|state| {
+        state.set_approval_for_all(api::caller(), operator, is_approved, None, api::time())
+    })
+}
+
+#[query(name = "isApprovedForAll")]
+fn is_approved_for_all(owner: Principal, operator: Principal) -> NftResult<bool> {
+    STATE.with_borrow(|state| Ok(state.is_approved_for_all(&owner, &operator)))
+}
+
+#[query(name = "operatorOf")]
+fn operator_of(token_id: Nat) -> NftResult<Option<Principal>> {
+    let token_id = to_u64(&token_id, NftError::TokenNotFound)?;
+    STATE.with_borrow(|state| Ok(state.live_token(token_id)?.metadata.operator))
+}
+
+#[update(name = "transfer")]
+fn transfer(to: Principal, token_id: Nat) -> NftResult<Nat> {
+    let token_id = to_u64(&token_id, NftError::TokenNotFound)?;
+    let caller = api::caller();
+    STATE.with_borrow_mut(|state| {
+        state.transfer_from(caller, caller, to, token_id, None, api::time())
+    })
+}
+
+#[update(name = "transferFrom")]
+fn transfer_from(from: Principal, to: Principal, token_id: Nat) -> NftResult<Nat> {
+    let token_id = to_u64(&token_id, NftError::TokenNotFound)?;
+    STATE.with_borrow_mut(|state| {
+        state.transfer_from(api::caller(), from, to, token_id, None, api::time())
+    })
+}
+
+#[query(name = "metadata")]
+fn metadata() -> Metadata {
+    STATE.with_borrow(|state| {
+        let metadata = &state.canister_metadata;
+        Metadata {
+            logo: metadata.logo.clone(),
+            name: metadata.name.clone(),
+            created_at: metadata.created_at,
+            upgraded_at: metadata.upgraded_at,
+            custodians: metadata.custodians.iter().copied().collect(),
+            symbol: metadata.symbol.clone(),
         }
     })
 }
 
+#[query(name = "stats")]
+fn stats() -> DipStats {
+    STATE.with_borrow(|state| DipStats {
+        cycles: Nat::from(api::canister_balance128()),
+        total_transactions: Nat::from(state.stats.total_transactions),
+        total_unique_holders: Nat::from(state.stats.total_unique_holders),
+        total_supply: Nat::from(state.stats.total_supply),
+    })
+}
+
+#[query(name = "supportedInterfaces")]
+fn supported_interfaces() -> Vec<SupportedInterface> {
+    vec![
+        SupportedInterface::Approval,
+        SupportedInterface::Mint,
+        SupportedInterface::Burn,
+        SupportedInterface::TransactionHistory,
+    ]
+}
+
+#[query(name = "transaction")]
+fn transaction(tx_id: Nat) -> NftResult<TxEvent> {
+    let tx_id = to_u64(&tx_id, NftError::TxNotFound)?;
+    STATE.with_borrow(|state| {
+        state
+            .transactions
+            .get(tx_id as usize)
+            .cloned()
+            .ok_or(NftError::TxNotFound)
+    })
+}
+
+#[query(name = "getTokenTransactions")]
+fn get_token_transactions(token_id: u64, offset: u64, limit: u64) -> TxHistoryPage {
+    STATE.with_borrow(|state| state.token_history(token_id, offset, limit))
+}
+
+#[query(name = "getPrincipalTransactions")]
+fn get_principal_transactions(principal: Principal, offset: u64, limit: u64) -> TxHistoryPage {
+    STATE.with_borrow(|state| state.principal_history(&principal, offset, limit))
+}
+
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    pub(crate) fn token(owner: Principal, space_id: u64, num_units: u64) -> Token {
+        Token {
+            metadata: TokenMetaData {
+                owner,
+                is_burned: false,
+                properties: vec![(String::from("rooms"), GenericValue::Nat64Content(num_units))],
+                burned_at: None,
+                burned_by: None,
+                minted_at: 1,
+                minted_by: owner,
+                space_id,
+                num_units,
+                operator: None,
+                approved_at: None,
+                approved_by: None,
+                transferred_at: None,
+                transferred_by: None,
+            },
+            data: TokenData {
+                bytes: vec![1, 2, 3],
+                data_type: DataType::Raw,
+            },
+        }
+    }
+
+    pub(crate) fn mint_args(owner: Principal, space_id: u64, num_units: u64) -> MintArgs {
+        MintArgs {
+            owner,
+            properties: None,
+            space_id,
+            token_data: TokenData {
+                bytes: vec![],
+                data_type: DataType::Raw,
+            },
+            num_units,
+        }
+    }
+
+    #[test]
+    fn state_survives_stable_memory_encoding() {
+        let mut state = State::default();
+        state.canister_metadata.name = Some(String::from("propspace"));
+        state.canister_metadata.custodians = HashSet::from([principal(0)]);
+        state.canister_metadata.created_at = 1;
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.tokens.insert(2, token(principal(2), 1, 6));
+        state.owners.insert(principal(1), HashSet::from([1]));
+        state.owners.insert(principal(2), HashSet::from([2]));
+        state.spaces.insert(
+            1,
+            Space {
+                id: 1,
+                price_per_unit: 100,
+                num_units_available: 10,
+            },
+        );
+        state.stats.total_supply = 2;
+        state.stats.total_spaces = 1;
+
+        let bytes = candid::encode_args((&state,)).unwrap();
+        let (restored,): (State,) = candid::decode_args(&bytes).unwrap();
+
+        assert_eq!(
+            restored.canister_metadata.name,
+            state.canister_metadata.name
+        );
+        assert_eq!(
+            restored.canister_metadata.custodians,
+            state.canister_metadata.custodians
+        );
+        assert_eq!(restored.owners, state.owners);
+        assert_eq!(restored.tokens.len(), 2);
+        let restored_token = &restored.tokens[&2].metadata;
+        assert_eq!(restored_token.owner, principal(2));
+        assert_eq!(restored_token.num_units, 6);
+        assert_eq!(restored.tokens[&2].data.bytes, vec![1, 2, 3]);
+        assert_eq!(restored.spaces[&1].price_per_unit, 100);
+        assert_eq!(restored.stats.total_supply, 2);
+        assert_eq!(restored.stats.total_spaces, 1);
+    }
+
+    #[test]
+    fn traded_units_are_credited_to_the_receiver() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.owners.insert(principal(1), HashSet::from([1]));
+        state.stats.total_supply = 1;
+        state.verified_until.insert(principal(1), 10);
+
+        assert!(matches!(
+            state.trade_units(1, principal(1), principal(2), 3, principal(0), 2),
+            Err(NftError::NotVerified)
+        ));
+        state.verified_until.insert(principal(2), 10);
+
+        //a custodian needs the owner's approval like any other operator
+        state.canister_metadata.custodians.insert(principal(5));
+        assert!(matches!(
+            state.trade_units(1, principal(1), principal(2), 3, principal(5), 2),
+            Err(NftError::OperatorNotFound)
+        ));
+
+        //principal(0) trades on the owner's behalf once approved as an operator
+        assert!(matches!(
+            state.trade_units(1, principal(1), principal(2), 3, principal(0), 2),
+            Err(NftError::OperatorNotFound)
+        ));
+        state.tokens.get_mut(&1).unwrap().metadata.operator = Some(principal(3));
+        assert!(matches!(
+            state.trade_units(1, principal(1), principal(2), 3, principal(0), 2),
+            Err(NftError::UnauthorizedOperator)
+        ));
+        state
+            .operator_approvals
+            .insert(principal(1), HashSet::from([principal(0)]));
+        assert_eq!(
+            state
+                .trade_units(1, principal(1), principal(2), 3, principal(0), 2)
+                .ok(),
+            Some(2)
+        );
+        assert_eq!(state.tokens[&1].metadata.num_units, 1);
+        assert_eq!(state.tokens[&2].metadata.owner, principal(2));
+        assert_eq!(state.tokens[&2].metadata.num_units, 3);
+        assert_eq!(state.stats.total_unique_holders, 2);
+
+        //the remaining unit merges into the receiver's token and the emptied token is burned
+        assert_eq!(
+            state
+                .trade_units(1, principal(1), principal(2), 1, principal(0), 3)
+                .ok(),
+            Some(2)
+        );
+        assert_eq!(state.tokens[&2].metadata.num_units, 4);
+        assert!(state.tokens[&1].metadata.is_burned);
+        assert!(!state.owners.contains_key(&principal(1)));
+        assert_eq!(state.stats.total_supply, 1);
+        assert_eq!(state.stats.total_unique_holders, 1);
+        //two trades and the burn of the emptied token
+        assert_eq!(state.stats.total_transactions, 3);
+
+        assert!(state
+            .trade_units(1, principal(1), principal(2), 1, principal(0), 4)
+            .is_err());
+
+        let history = state.token_history(1, 0, 10);
+        assert_eq!(history.total, 3);
+        assert_eq!(history.transactions[2].event.operation, "burn");
+        let history = state.token_history(2, 1, 10);
+        assert_eq!(history.total, 2);
+        assert_eq!(history.transactions.len(), 1);
+        assert_eq!(history.transactions[0].tx_id, 1);
+        let history = state.principal_history(&principal(2), 0, 1);
+        assert_eq!(history.total, 2);
+        assert_eq!(history.transactions[0].event.operation, "tradeUnits");
+        assert_eq!(state.principal_history(&principal(3), 0, 10).total, 0);
+
+        //owners trade their own units directly
+        assert!(matches!(
+            state.trade_units(2, principal(2), principal(1), 1, principal(5), 5),
+            Err(NftError::OperatorNotFound)
+        ));
+        assert!(state
+            .trade_units(2, principal(2), principal(1), 1, principal(2), 5)
+            .is_ok());
+    }
+
+    #[test]
+    fn operators_can_transfer_approved_tokens() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.tokens.insert(2, token(principal(1), 1, 2));
+        state.owners.insert(principal(1), HashSet::from([1, 2]));
+        for id in 1..=3 {
+            state.verified_until.insert(principal(id), 10);
+        }
+
+        assert!(matches!(
+            state.approve(principal(1), principal(1), 1, None, 1),
+            Err(NftError::SelfApprove)
+        ));
+        assert!(matches!(
+            state.approve(principal(2), principal(3), 1, None, 1),
+            Err(NftError::UnauthorizedOwner)
+        ));
+        assert_eq!(
+            state.approve(principal(1), principal(2), 1, None, 1).ok(),
+            Some(Nat::from(0u64))
+        );
+        assert!(matches!(
+            state.transfer_from(principal(2), principal(1), principal(3), 2, None, 2),
+            Err(NftError::OperatorNotFound)
+        ));
+        assert_eq!(
+            state
+                .transfer_from(principal(2), principal(1), principal(3), 1, None, 2)
+                .ok(),
+            Some(Nat::from(1u64))
+        );
+        assert_eq!(state.tokens[&1].metadata.owner, principal(3));
+        assert_eq!(state.tokens[&1].metadata.operator, None);
+        assert_eq!(state.owners[&principal(3)], HashSet::from([1]));
+
+        assert!(state
+            .set_approval_for_all(principal(1), principal(2), true, None, 3)
+            .is_ok());
+        assert!(state.is_approved_for_all(&principal(1), &principal(2)));
+        assert!(state
+            .transfer_from(principal(2), principal(1), principal(3), 2, None, 3)
+            .is_ok());
+        assert!(!state.owners.contains_key(&principal(1)));
+        assert!(state
+            .set_approval_for_all(principal(1), principal(2), false, None, 4)
+            .is_ok());
+        assert!(matches!(
+            state.set_approval_for_all(principal(1), principal(2), false, None, 4),
+            Err(NftError::OperatorNotFound)
+        ));
+
+        assert_eq!(state.transactions.len(), 5);
+        assert_eq!(state.transactions[1].operation, "transferFrom");
+        assert_eq!(state.stats.total_transactions, 5);
+    }
+
+    #[test]
+    fn burning_removes_the_token_from_the_owner_index() {
+        let mut state = State::default();
+        state.tokens.insert(1, token(principal(1), 1, 4));
+        state.tokens.insert(2, token(principal(1), 2, 1));
+        state.add_owner_token(principal(1), 1);
+        state.add_owner_token(principal(1), 2);
+        state.stats.total_supply = 2;
+        assert_eq!(state.owners[&principal(1)], HashSet::from([1, 2]));
+
+        assert!(state.burn(2, principal(0), 1).is_ok());
+        assert!(state.burn(2, principal(0), 1).is_err());
+        assert_eq!(state.owners[&principal(1)], HashSet::from([1]));
+        assert_eq!(state.stats.total_supply, 1);
+
+        let report = state.check_owner_index();
+        assert!(report.missing.is_empty() && report.stale.is_empty());
+
+        state.owners.insert(principal(3), HashSet::from([2]));
+        state.tokens.insert(3, token(principal(1), 1, 1));
+        let report = state.check_owner_index();
+        assert_eq!(report.missing, vec![(principal(1), 3)]);
+        assert_eq!(report.stale, vec![(principal(3), 2)]);
+    }
+
+    #[test]
+    fn minting_draws_from_the_space_supply() {
+        let mut state = State::default();
+        state.spaces.insert(
+            1,
+            Space {
+                id: 1,
+                price_per_unit: 100,
+                num_units_available: 5,
+            },
+        );
+        assert!(state
+            .mint(mint_args(principal(1), 1, 6), principal(0), 1)
+            .is_err());
+        assert_eq!(
+            state
+                .mint(mint_args(principal(1), 1, 3), principal(0), 1)
+                .ok(),
+            Some(1)
+        );
+        assert_eq!(
+            state
+                .mint(mint_args(principal(1), 1, 2), principal(0), 1)
+                .ok(),
+            Some(2)
+        );
+        assert!(state
+            .mint(mint_args(principal(2), 1, 1), principal(0), 1)
+            .is_err());
+        assert_eq!(state.owners[&principal(1)], HashSet::from([1, 2]));
+
+        assert!(state.burn(1, principal(0), 2).is_ok());
+        let supply = state.space_supply(1).ok().unwrap();
+        assert_eq!(supply.units_minted, 2);
+        assert_eq!(supply.units_available, 3);
+        assert_eq!(
+            state.space_holdings(1).ok().unwrap(),
+            vec![SpaceHolding {
+                token_id: 2,
+                owner: principal(1),
+                num_units: 2,
+            }]
+        );
+        assert!(state.space_holdings(2).is_err());
+    }
+}
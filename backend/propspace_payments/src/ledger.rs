@@ -0,0 +1,783 @@
+use crate::types::*;
+use candid::{CandidType, Deserialize, Nat, Principal};
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_FEE_E8S: u64 = 10_000;
+pub const DECIMALS: u8 = 8;
+
+//how long operations with a created_at_time are remembered for deduplication
+const TX_WINDOW_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+//how far ahead of the ledger a client clock may be
+const PERMITTED_DRIFT_NANOS: u64 = 2 * 60 * 1_000_000_000;
+const MAX_MEMO_LENGTH: usize = 32;
+
+//an operation submitted with a created_at_time, identified by its caller and encoded arguments
+#[derive(Clone, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub struct RecentOperation {
+    pub caller: Principal,
+    pub kind: TransactionKind,
+    pub args: Vec<u8>,
+    pub created_at_time: u64,
+}
+
+#[derive(Clone, Copy, CandidType, Deserialize)]
+pub struct StoredAllowance {
+    amount_e8s: u64,
+    expires_at: Option<u64>,
+}
+
+//rejections shared by transfers and approvals, mapped onto the error type of each method
+enum Rejection {
+    BadFee { expected_fee: u64 },
+    InsufficientFunds { balance: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: u64 },
+    Generic(&'static str),
+}
+
+impl From<Rejection> for TransferError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::BadFee { expected_fee } => TransferError::BadFee {
+                expected_fee: Nat::from(expected_fee),
+            },
+            Rejection::InsufficientFunds { balance } => TransferError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            Rejection::TooOld => TransferError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                TransferError::CreatedInFuture { ledger_time }
+            }
+            Rejection::Duplicate { duplicate_of } => TransferError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            Rejection::Generic(message) => TransferError::GenericError {
+                error_code: Nat::from(0u64),
+                message: String::from(message),
+            },
+        }
+    }
+}
+
+impl From<Rejection> for ApproveError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::BadFee { expected_fee } => ApproveError::BadFee {
+                expected_fee: Nat::from(expected_fee),
+            },
+            Rejection::InsufficientFunds { balance } => ApproveError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            Rejection::TooOld => ApproveError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                ApproveError::CreatedInFuture { ledger_time }
+            }
+            Rejection::Duplicate { duplicate_of } => ApproveError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            Rejection::Generic(message) => ApproveError::GenericError {
+                error_code: Nat::from(0u64),
+                message: String::from(message),
+            },
+        }
+    }
+}
+
+impl From<Rejection> for TransferFromError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::BadFee { expected_fee } => TransferFromError::BadFee {
+                expected_fee: Nat::from(expected_fee),
+            },
+            Rejection::InsufficientFunds { balance } => TransferFromError::InsufficientFunds {
+                balance: Nat::from(balance),
+            },
+            Rejection::TooOld => TransferFromError::TooOld,
+            Rejection::CreatedInFuture { ledger_time } => {
+                TransferFromError::CreatedInFuture { ledger_time }
+            }
+            Rejection::Duplicate { duplicate_of } => TransferFromError::Duplicate {
+                duplicate_of: Nat::from(duplicate_of),
+            },
+            Rejection::Generic(message) => TransferFromError::GenericError {
+                error_code: Nat::from(0u64),
+                message: String::from(message),
+            },
+        }
+    }
+}
+
+//internal ledger of account balances
+//every balance change is appended to the transaction log, which is never rewritten
+#[derive(CandidType, Deserialize)]
+pub struct Ledger {
+    pub custodians: HashSet<Principal>,
+    pub name: String,
+    pub symbol: String,
+    pub fee_e8s: u64,
+    pub balances: HashMap<Account, u64>,
+    //keyed by (owner account, spender account)
+    pub allowances: HashMap<(Account, Account), StoredAllowance>,
+    pub total_supply: u64,
+    pub transactions: Vec<Transaction>,
+    pub recent_operations: HashMap<RecentOperation, u64>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Self {
+            custodians: HashSet::new(),
+            name: String::from("Propspace Token"),
+            symbol: String::from("PST"),
+            fee_e8s: DEFAULT_FEE_E8S,
+            balances: HashMap::new(),
+            allowances: HashMap::new(),
+            total_supply: 0,
+            transactions: vec![],
+            recent_operations: HashMap::new(),
+        }
+    }
+}
+
+impl Ledger {
+    pub fn is_custodian(&self, principal: &Principal) -> bool {
+        self.custodians.contains(principal)
+    }
+
+    pub fn balance_of(&self, account: Account) -> u64 {
+        self.balances
+            .get(&account.normalized())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn metadata(&self) -> Vec<(String, MetadataValue)> {
+        vec![
+            (
+                String::from("icrc1:name"),
+                MetadataValue::Text(self.name.clone()),
+            ),
+            (
+                String::from("icrc1:symbol"),
+                MetadataValue::Text(self.symbol.clone()),
+            ),
+            (
+                String::from("icrc1:decimals"),
+                MetadataValue::Nat(Nat::from(DECIMALS)),
+            ),
+            (
+                String::from("icrc1:fee"),
+                MetadataValue::Nat(Nat::from(self.fee_e8s)),
+            ),
+        ]
+    }
+
+    //credits newly issued funds to an account
+    pub fn mint(&mut self, to: Account, amount_e8s: u64, now: u64) -> Result<u64, TransferError> {
+        if !to.is_valid() {
+            return Err(Rejection::Generic("invalid subaccount").into());
+        }
+        if amount_e8s == 0 {
+            return Err(Rejection::Generic("amount must be greater than zero").into());
+        }
+
+        let to = to.normalized();
+        self.total_supply = self
+            .total_supply
+            .checked_add(amount_e8s)
+            .ok_or(Rejection::Generic("total supply overflows"))?;
+        *self.balances.entry(to.clone()).or_default() += amount_e8s;
+
+        Ok(self.record(Transaction {
+            id: 0,
+            kind: TransactionKind::Mint,
+            from: None,
+            to: Some(to),
+            spender: None,
+            amount_e8s,
+            fee_e8s: 0,
+            memo: None,
+            created_at_time: None,
+            expires_at: None,
+            timestamp: now,
+        }))
+    }
+
+    pub fn transfer(
+        &mut self,
+        caller: Principal,
+        arg: TransferArg,
+        now: u64,
+    ) -> Result<u64, TransferError> {
+        let from = account(caller, arg.from_subaccount.clone())?;
+        let to = valid_account(arg.to.clone())?;
+        validate_memo(&arg.memo)?;
+        self.check_fee(&arg.fee)?;
+        let amount_e8s = match u64::try_from(&arg.amount.0) {
+            Ok(amount) => amount,
+            Err(_) => {
+                return Err(Rejection::InsufficientFunds {
+                    balance: self.balance_of(from),
+                }
+                .into())
+            }
+        };
+        let operation = self.deduplicate(
+            caller,
+            TransactionKind::Transfer,
+            &arg,
+            arg.created_at_time,
+            now,
+        )?;
+
+        self.move_funds(&from, &to, amount_e8s)?;
+        let tx_id = self.record(Transaction {
+            id: 0,
+            kind: TransactionKind::Transfer,
+            from: Some(from),
+            to: Some(to),
+            spender: None,
+            amount_e8s,
+            fee_e8s: self.fee_e8s,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+            expires_at: None,
+            timestamp: now,
+        });
+        self.remember(operation, tx_id);
+        Ok(tx_id)
+    }
+
+    //transfer between default accounts for clients of the wallet endpoints, charged the same fee
+    pub fn transfer_funds(
+        &mut self,
+        caller: Principal,
+        receiver: Principal,
+        amount_e8s: u64,
+        now: u64,
+    ) -> PaymentResult<u64> {
+        if amount_e8s == 0 {
+            return Err(PaymentError::InvalidAmount);
+        }
+        if caller == receiver {
+            return Err(PaymentError::SelfTransfer);
+        }
+
+        let arg = TransferArg {
+            from_subaccount: None,
+            to: Account::from(receiver),
+            amount: Nat::from(amount_e8s),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        };
+        match self.transfer(caller, arg, now) {
+            Ok(tx_id) => Ok(tx_id),
+            Err(TransferError::InsufficientFunds { .. }) => Err(PaymentError::InsufficientFunds {
+                balance_e8s: self.balance_of(Account::from(caller)),
+            }),
+            //no other rejection applies to a transfer without fee, memo or created_at_time
+            Err(_) => Err(PaymentError::InvalidAmount),
+        }
+    }
+
+    pub fn allowance(&self, args: AllowanceArgs, now: u64) -> Allowance {
+        let key = (args.account.normalized(), args.spender.normalized());
+        match self.allowances.get(&key) {
+            Some(allowance) if allowance.expires_at.is_none_or(|e| e > now) => Allowance {
+                allowance: Nat::from(allowance.amount_e8s),
+                expires_at: allowance.expires_at,
+            },
+            _ => Allowance {
+                allowance: Nat::from(0u64),
+                expires_at: None,
+            },
+        }
+    }
+
+    //sets, rather than adds to, the amount the spender may transfer out of the caller's account
+    //the fee is charged to the caller
+    pub fn approve(
+        &mut self,
+        caller: Principal,
+        arg: ApproveArgs,
+        now: u64,
+    ) -> Result<u64, ApproveError> {
+        let from = account(caller, arg.from_subaccount.clone())?;
+        let spender = valid_account(arg.spender.clone())?;
+        if from.owner == spender.owner {
+            return Err(Rejection::Generic("cannot approve yourself as a spender").into());
+        }
+        validate_memo(&arg.memo)?;
+        self.check_fee(&arg.fee)?;
+        if arg.expires_at.is_some_and(|e| e <= now) {
+            return Err(ApproveError::Expired { ledger_time: now });
+        }
+        let operation = self.deduplicate(
+            caller,
+            TransactionKind::Approve,
+            &arg,
+            arg.created_at_time,
+            now,
+        )?;
+
+        let current_allowance = self
+            .allowance(
+                AllowanceArgs {
+                    account: from.clone(),
+                    spender: spender.clone(),
+                },
+                now,
+            )
+            .allowance;
+        if let Some(expected_allowance) = arg.expected_allowance {
+            if expected_allowance != current_allowance {
+                return Err(ApproveError::AllowanceChanged { current_allowance });
+            }
+        }
+
+        let balance = self.balance_of(from.clone());
+        if balance < self.fee_e8s {
+            return Err(Rejection::InsufficientFunds { balance }.into());
+        }
+        self.set_balance(from.clone(), balance - self.fee_e8s);
+        self.total_supply -= self.fee_e8s;
+
+        //allowances beyond the representable supply are capped
+        let amount_e8s = u64::try_from(&arg.amount.0).unwrap_or(u64::MAX);
+        let key = (from.clone(), spender.clone());
+        if amount_e8s == 0 {
+            self.allowances.remove(&key);
+        } else {
+            self.allowances.insert(
+                key,
+                StoredAllowance {
+                    amount_e8s,
+                    expires_at: arg.expires_at,
+                },
+            );
+        }
+
+        let tx_id = self.record(Transaction {
+            id: 0,
+            kind: TransactionKind::Approve,
+            from: Some(from),
+            to: None,
+            spender: Some(spender),
+            amount_e8s,
+            fee_e8s: self.fee_e8s,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+            expires_at: arg.expires_at,
+            timestamp: now,
+        });
+        self.remember(operation, tx_id);
+        Ok(tx_id)
+    }
+
+    //moves funds out of an account on its owner's behalf, drawing the amount and fee from the allowance
+    pub fn transfer_from(
+        &mut self,
+        caller: Principal,
+        arg: TransferFromArgs,
+        now: u64,
+    ) -> Result<u64, TransferFromError> {
+        let spender = account(caller, arg.spender_subaccount.clone())?;
+        let from = valid_account(arg.from.clone())?;
+        let to = valid_account(arg.to.clone())?;
+        validate_memo(&arg.memo)?;
+        self.check_fee(&arg.fee)?;
+        let amount_e8s = match u64::try_from(&arg.amount.0) {
+            Ok(amount) => amount,
+            Err(_) => {
+                return Err(Rejection::InsufficientFunds {
+                    balance: self.balance_of(from),
+                }
+                .into())
+            }
+        };
+        let operation = self.deduplicate(
+            caller,
+            TransactionKind::TransferFrom,
+            &arg,
+            arg.created_at_time,
+            now,
+        )?;
+
+        //an owner moving its own funds needs no allowance
+        let key = (from.clone(), spender.clone());
+        let debit = amount_e8s.saturating_add(self.fee_e8s);
+        if from != spender {
+            let allowance = self
+                .allowance(
+                    AllowanceArgs {
+                        account: from.clone(),
+                        spender: spender.clone(),
+                    },
+                    now,
+                )
+                .allowance;
+            if u64::try_from(&allowance.0).unwrap_or(u64::MAX) < debit {
+                return Err(TransferFromError::InsufficientAllowance { allowance });
+            }
+        }
+
+        self.move_funds(&from, &to, amount_e8s)?;
+        if let Some(allowance) = self.allowances.get_mut(&key) {
+            allowance.amount_e8s -= debit;
+            if allowance.amount_e8s == 0 {
+                self.allowances.remove(&key);
+            }
+        }
+
+        let tx_id = self.record(Transaction {
+            id: 0,
+            kind: TransactionKind::TransferFrom,
+            from: Some(from),
+            to: Some(to),
+            spender: Some(spender),
+            amount_e8s,
+            fee_e8s: self.fee_e8s,
+            memo: arg.memo,
+            created_at_time: arg.created_at_time,
+            expires_at: None,
+            timestamp: now,
+        });
+        self.remember(operation, tx_id);
+        Ok(tx_id)
+    }
+
+    pub fn history(&self, principal: &Principal) -> Vec<Transaction> {
+        let involves = |account: &Option<Account>| {
+            account
+                .as_ref()
+                .is_some_and(|account| account.owner == *principal)
+        };
+        self.transactions
+            .iter()
+            .filter(|tx| involves(&tx.from) || involves(&tx.to) || involves(&tx.spender))
+            .cloned()
+            .collect()
+    }
+
+    fn check_fee(&self, fee: &Option<Nat>) -> Result<(), Rejection> {
+        match fee {
+            Some(fee) if u64::try_from(&fee.0) != Ok(self.fee_e8s) => Err(Rejection::BadFee {
+                expected_fee: self.fee_e8s,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    //debits the amount and the fee from one account and credits the amount to the other
+    //the fee is burned
+    fn move_funds(
+        &mut self,
+        from: &Account,
+        to: &Account,
+        amount_e8s: u64,
+    ) -> Result<(), Rejection> {
+        let balance = self.balance_of(from.clone());
+        let debit = match amount_e8s.checked_add(self.fee_e8s) {
+            Some(debit) if debit <= balance => debit,
+            _ => return Err(Rejection::InsufficientFunds { balance }),
+        };
+
+        self.set_balance(from.clone(), balance - debit);
+        *self.balances.entry(to.clone()).or_default() += amount_e8s;
+        self.total_supply -= self.fee_e8s;
+        Ok(())
+    }
+
+    fn set_balance(&mut self, account: Account, balance: u64) {
+        if balance == 0 {
+            self.balances.remove(&account);
+        } else {
+            self.balances.insert(account, balance);
+        }
+    }
+
+    //checks created_at_time against the deduplication window
+    //returns the operation to remember once it has been applied
+    fn deduplicate<T: CandidType>(
+        &mut self,
+        caller: Principal,
+        kind: TransactionKind,
+        args: &T,
+        created_at_time: Option<u64>,
+        now: u64,
+    ) -> Result<Option<RecentOperation>, Rejection> {
+        let created_at_time = match created_at_time {
+            Some(created_at_time) => created_at_time,
+            None => return Ok(None),
+        };
+
+        if created_at_time.saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS) < now {
+            return Err(Rejection::TooOld);
+        }
+        if created_at_time > now.saturating_add(PERMITTED_DRIFT_NANOS) {
+            return Err(Rejection::CreatedInFuture { ledger_time: now });
+        }
+
+        self.recent_operations.retain(|operation, _| {
+            operation
+                .created_at_time
+                .saturating_add(TX_WINDOW_NANOS + PERMITTED_DRIFT_NANOS)
+                >= now
+        });
+
+        let operation = RecentOperation {
+            caller,
+            kind,
+            args: candid::encode_one(args).unwrap_or_default(),
+            created_at_time,
+        };
+        if let Some(tx_id) = self.recent_operations.get(&operation) {
+            return Err(Rejection::Duplicate {
+                duplicate_of: *tx_id,
+            });
+        }
+        Ok(Some(operation))
+    }
+
+    fn remember(&mut self, operation: Option<RecentOperation>, tx_id: u64) {
+        if let Some(operation) = operation {
+            self.recent_operations.insert(operation, tx_id);
+        }
+    }
+
+    fn record(&mut self, mut transaction: Transaction) -> u64 {
+        transaction.id = self.transactions.len() as u64;
+        let id = transaction.id;
+        self.transactions.push(transaction);
+        id
+    }
+}
+
+fn valid_account(account: Account) -> Result<Account, Rejection> {
+    if account.is_valid() {
+        Ok(account.normalized())
+    } else {
+        Err(Rejection::Generic("invalid subaccount"))
+    }
+}
+
+fn account(owner: Principal, subaccount: Option<Subaccount>) -> Result<Account, Rejection> {
+    valid_account(Account { owner, subaccount })
+}
+
+fn validate_memo(memo: &Option<Vec<u8>>) -> Result<(), Rejection> {
+    if memo.as_ref().is_some_and(|m| m.len() > MAX_MEMO_LENGTH) {
+        return Err(Rejection::Generic("memo is longer than 32 bytes"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn transfer_arg(to: Account, amount: u64) -> TransferArg {
+        TransferArg {
+            from_subaccount: None,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn approve_args(spender: Account, amount: u64) -> ApproveArgs {
+        ApproveArgs {
+            from_subaccount: None,
+            spender,
+            amount: Nat::from(amount),
+            expected_allowance: None,
+            expires_at: None,
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    fn transfer_from_args(from: Account, to: Account, amount: u64) -> TransferFromArgs {
+        TransferFromArgs {
+            spender_subaccount: None,
+            from,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: None,
+            created_at_time: None,
+        }
+    }
+
+    #[test]
+    fn transfers_move_funds_burn_the_fee_and_are_logged() {
+        let mut ledger = Ledger::default();
+        assert_eq!(ledger.mint(Account::from(principal(1)), 100_000, 1), Ok(0));
+
+        assert_eq!(
+            ledger.transfer(
+                principal(1),
+                transfer_arg(Account::from(principal(2)), 95_000),
+                2
+            ),
+            Err(TransferError::InsufficientFunds {
+                balance: Nat::from(100_000u64)
+            })
+        );
+
+        let mut arg = transfer_arg(Account::from(principal(2)), 50_000);
+        arg.fee = Some(Nat::from(1u64));
+        assert_eq!(
+            ledger.transfer(principal(1), arg, 2),
+            Err(TransferError::BadFee {
+                expected_fee: Nat::from(DEFAULT_FEE_E8S)
+            })
+        );
+
+        let to = Account {
+            owner: principal(2),
+            subaccount: Some(DEFAULT_SUBACCOUNT.to_vec()),
+        };
+        assert_eq!(
+            ledger.transfer(principal(1), transfer_arg(to, 50_000), 2),
+            Ok(1)
+        );
+
+        assert_eq!(ledger.balance_of(Account::from(principal(1))), 40_000);
+        assert_eq!(ledger.balance_of(Account::from(principal(2))), 50_000);
+        assert_eq!(ledger.total_supply, 90_000);
+        assert_eq!(ledger.history(&principal(1)).len(), 2);
+        assert_eq!(
+            ledger.history(&principal(2))[0].from,
+            Some(Account::from(principal(1)))
+        );
+    }
+
+    #[test]
+    fn wallet_transfers_move_funds_between_default_accounts() {
+        let mut ledger = Ledger::default();
+        ledger.mint(Account::from(principal(1)), 100_000, 1).ok();
+
+        assert_eq!(
+            ledger.transfer_funds(principal(1), principal(1), 10, 2),
+            Err(PaymentError::SelfTransfer)
+        );
+        assert_eq!(
+            ledger.transfer_funds(principal(1), principal(2), 0, 2),
+            Err(PaymentError::InvalidAmount)
+        );
+        assert_eq!(
+            ledger.transfer_funds(principal(1), principal(2), 95_000, 2),
+            Err(PaymentError::InsufficientFunds {
+                balance_e8s: 100_000
+            })
+        );
+        assert_eq!(
+            ledger.transfer_funds(principal(1), principal(2), 50_000, 2),
+            Ok(1)
+        );
+        assert_eq!(ledger.balance_of(Account::from(principal(1))), 40_000);
+        assert_eq!(ledger.balance_of(Account::from(principal(2))), 50_000);
+    }
+
+    #[test]
+    fn transfers_with_created_at_time_are_deduplicated() {
+        let now = TX_WINDOW_NANOS * 2;
+        let mut ledger = Ledger::default();
+        ledger.mint(Account::from(principal(1)), 100_000, now).ok();
+
+        let mut arg = transfer_arg(Account::from(principal(2)), 10_000);
+        arg.created_at_time = Some(now);
+        arg.memo = Some(vec![1]);
+        assert_eq!(ledger.transfer(principal(1), arg.clone(), now), Ok(1));
+        assert_eq!(
+            ledger.transfer(principal(1), arg.clone(), now + 1),
+            Err(TransferError::Duplicate {
+                duplicate_of: Nat::from(1u64)
+            })
+        );
+
+        arg.memo = Some(vec![2]);
+        assert_eq!(ledger.transfer(principal(1), arg.clone(), now + 1), Ok(2));
+
+        arg.created_at_time = Some(0);
+        assert_eq!(
+            ledger.transfer(principal(1), arg.clone(), now),
+            Err(TransferError::TooOld)
+        );
+        arg.created_at_time = Some(now + PERMITTED_DRIFT_NANOS + 1);
+        assert_eq!(
+            ledger.transfer(principal(1), arg, now),
+            Err(TransferError::CreatedInFuture { ledger_time: now })
+        );
+    }
+
+    #[test]
+    fn spenders_transfer_within_their_allowance() {
+        let owner = Account::from(principal(1));
+        let spender = Account::from(principal(2));
+        let receiver = Account::from(principal(3));
+        let mut ledger = Ledger::default();
+        ledger.mint(owner.clone(), 100_000, 1).ok();
+
+        assert_eq!(
+            ledger.transfer_from(
+                principal(2),
+                transfer_from_args(owner.clone(), receiver.clone(), 10_000),
+                2
+            ),
+            Err(TransferFromError::InsufficientAllowance {
+                allowance: Nat::from(0u64)
+            })
+        );
+
+        let mut args = approve_args(spender.clone(), 30_000);
+        args.expires_at = Some(10);
+        assert_eq!(ledger.approve(principal(1), args.clone(), 2), Ok(1));
+        assert_eq!(ledger.balance_of(owner.clone()), 90_000);
+
+        args.expected_allowance = Some(Nat::from(0u64));
+        assert_eq!(
+            ledger.approve(principal(1), args, 3),
+            Err(ApproveError::AllowanceChanged {
+                current_allowance: Nat::from(30_000u64)
+            })
+        );
+
+        assert_eq!(
+            ledger.transfer_from(
+                principal(2),
+                transfer_from_args(owner.clone(), receiver.clone(), 10_000),
+                4
+            ),
+            Ok(2)
+        );
+        let remaining = ledger.allowance(
+            AllowanceArgs {
+                account: owner.clone(),
+                spender: spender.clone(),
+            },
+            5,
+        );
+        assert_eq!(remaining.allowance, Nat::from(10_000u64));
+        assert_eq!(ledger.balance_of(owner.clone()), 70_000);
+        assert_eq!(ledger.balance_of(receiver.clone()), 10_000);
+
+        //the allowance lapses once it expires
+        assert_eq!(
+            ledger.transfer_from(principal(2), transfer_from_args(owner, receiver, 1), 10),
+            Err(TransferFromError::InsufficientAllowance {
+                allowance: Nat::from(0u64)
+            })
+        );
+    }
+}
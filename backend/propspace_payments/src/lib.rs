@@ -1,36 +1,219 @@
-use candid::Principal;
-use ic_cdk;
-use ic_cdk::export::candid::candid_method;
-use ic_cdk::export::candid::export_service;
-
+use crate::ledger::Ledger;
+use crate::types::*;
+use candid::{Nat, Principal};
+use ic_cdk_macros::{init, post_upgrade, pre_upgrade};
 use std::cell::RefCell;
+use std::collections::HashSet;
+
+mod ledger;
+mod types;
+
+thread_local! {
+    static LEDGER: RefCell<Ledger> = RefCell::default();
+}
+
+#[init]
+fn init(args: Option<InitArguments>) {
+    let default_custodians = HashSet::from([ic_cdk::caller()]);
+    LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        match args {
+            Some(args) => {
+                ledger.custodians = args.custodians.unwrap_or(default_custodians);
+                if let Some(name) = args.name {
+                    ledger.name = name;
+                }
+                if let Some(symbol) = args.symbol {
+                    ledger.symbol = symbol;
+                }
+                if let Some(fee) = args.fee {
+                    ledger.fee_e8s = fee;
+                }
+            }
+            None => ledger.custodians = default_custodians,
+        }
+    });
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    LEDGER.with(|ledger| {
+        ic_cdk::storage::stable_save((&*ledger.borrow(),))
+            .expect("failed to save ledger to stable memory")
+    });
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    let (ledger,): (Ledger,) =
+        ic_cdk::storage::stable_restore().expect("failed to restore ledger from stable memory");
+    LEDGER.with(|l| *l.borrow_mut() = ledger);
+}
+
+fn is_custodian() -> Result<(), String> {
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .is_custodian(&ic_cdk::caller())
+            .then_some(())
+            .ok_or(String::from("You are not a custodian in this canister"))
+    })
+}
 
 #[ic_cdk::query]
-#[ic_cdk::export::candid::candid_method]
-fn create_wallet(principal: Principal) -> String {
-    unimplemented!();
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_name() -> String {
+    LEDGER.with(|ledger| ledger.borrow().name.clone())
 }
 
 #[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_symbol() -> String {
+    LEDGER.with(|ledger| ledger.borrow().symbol.clone())
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_decimals() -> u8 {
+    ledger::DECIMALS
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_fee() -> Nat {
+    LEDGER.with(|ledger| Nat::from(ledger.borrow().fee_e8s))
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_metadata() -> Vec<(String, MetadataValue)> {
+    LEDGER.with(|ledger| ledger.borrow().metadata())
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_total_supply() -> Nat {
+    LEDGER.with(|ledger| Nat::from(ledger.borrow().total_supply))
+}
+
+//funds are issued by custodians through mint rather than from a minting account
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_minting_account() -> Option<Account> {
+    None
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_balance_of(account: Account) -> Nat {
+    LEDGER.with(|ledger| Nat::from(ledger.borrow().balance_of(account)))
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    vec![
+        StandardRecord {
+            name: String::from("ICRC-1"),
+            url: String::from("https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1"),
+        },
+        StandardRecord {
+            name: String::from("ICRC-2"),
+            url: String::from("https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2"),
+        },
+    ]
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow_mut()
+            .transfer(ic_cdk::caller(), arg, ic_cdk::api::time())
+            .map(Nat::from)
+    })
+}
+
+#[ic_cdk::update]
 #[ic_cdk::export::candid::candid_method]
-fn check_balance(principal: Principal) -> u64 {
-    unimplemented!()
+fn icrc2_approve(arg: ApproveArgs) -> Result<Nat, ApproveError> {
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow_mut()
+            .approve(ic_cdk::caller(), arg, ic_cdk::api::time())
+            .map(Nat::from)
+    })
 }
 
 #[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn icrc2_allowance(arg: AllowanceArgs) -> Allowance {
+    LEDGER.with(|ledger| ledger.borrow().allowance(arg, ic_cdk::api::time()))
+}
+
+#[ic_cdk::update]
+#[ic_cdk::export::candid::candid_method]
+fn icrc2_transfer_from(arg: TransferFromArgs) -> Result<Nat, TransferFromError> {
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow_mut()
+            .transfer_from(ic_cdk::caller(), arg, ic_cdk::api::time())
+            .map(Nat::from)
+    })
+}
+
+//every principal already has a default account, so this only reports it and never fails
+#[ic_cdk::update]
 #[ic_cdk::export::candid::candid_method]
-fn transfer_funds(sender: Principal, receiver: Principal, amount: u64) -> String {
-    // Logic to transfer 'amount' of funds from 'sender' to 'receiver'
-    // Return a confirmation message indicating the success or failure of the transfer
-    // Example: "Transfer of {} ICP from {} to {} successful!", amount, sender, receiver
-    unimplemented!();
+fn create_wallet() -> PaymentResult<Wallet> {
+    LEDGER.with(|ledger| {
+        let caller = ic_cdk::caller();
+        Ok(Wallet {
+            owner: caller,
+            balance_e8s: ledger.borrow().balance_of(Account::from(caller)),
+            created_at: ic_cdk::api::time(),
+        })
+    })
 }
 
+//balance of the principal's default account
 #[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
+fn check_balance(principal: Principal) -> PaymentResult<u64> {
+    LEDGER.with(|ledger| Ok(ledger.borrow().balance_of(Account::from(principal))))
+}
+
+//transfers between default accounts, charged the icrc1_transfer fee,
+//returns the id of the transaction in the log
+#[ic_cdk::update]
 #[ic_cdk::export::candid::candid_method]
+fn transfer_funds(receiver: Principal, amount_e8s: u64) -> PaymentResult<u64> {
+    LEDGER.with(|ledger| {
+        ledger.borrow_mut().transfer_funds(
+            ic_cdk::caller(),
+            receiver,
+            amount_e8s,
+            ic_cdk::api::time(),
+        )
+    })
+}
+
+#[ic_cdk::update(guard = "is_custodian")]
+#[ic_cdk::export::candid::candid_method]
+fn mint(to: Account, amount_e8s: u64) -> Result<Nat, TransferError> {
+    LEDGER.with(|ledger| {
+        ledger
+            .borrow_mut()
+            .mint(to, amount_e8s, ic_cdk::api::time())
+            .map(Nat::from)
+    })
+}
+
+#[ic_cdk::query]
+#[ic_cdk::export::candid::candid_method(query)]
 fn transaction_history(principal: Principal) -> Vec<Transaction> {
-    // Logic to retrieve transaction history for the user identified by 'principal'
-    // Return a vector of Transaction structs containing details such as date, amount, sender, receiver, etc.
+    LEDGER.with(|ledger| ledger.borrow().history(&principal))
 }
 
 ic_cdk::export::candid::export_service!();
@@ -0,0 +1,187 @@
+use candid::{CandidType, Deserialize, Int, Nat, Principal};
+use std::collections::HashSet;
+
+pub type Subaccount = Vec<u8>;
+
+pub const DEFAULT_SUBACCOUNT: [u8; 32] = [0; 32];
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Subaccount>,
+}
+
+impl Account {
+    //the all-zero subaccount and no subaccount are the same account
+    pub fn normalized(mut self) -> Self {
+        if self.subaccount.as_deref() == Some(&DEFAULT_SUBACCOUNT[..]) {
+            self.subaccount = None;
+        }
+        self
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.subaccount.as_ref().is_none_or(|s| s.len() == 32)
+    }
+}
+
+impl From<Principal> for Account {
+    fn from(owner: Principal) -> Self {
+        Self {
+            owner,
+            subaccount: None,
+        }
+    }
+}
+
+//a principal's default account as seen by the wallet endpoints that predate icrc-1
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Wallet {
+    pub owner: Principal,
+    pub balance_e8s: u64,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum PaymentError {
+    WalletNotFound,
+    WalletExists,
+    InsufficientFunds { balance_e8s: u64 },
+    InvalidAmount,
+    SelfTransfer,
+}
+
+pub type PaymentResult<T = ()> = Result<T, PaymentError>;
+
+#[derive(CandidType, Deserialize)]
+pub struct InitArguments {
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub fee: Option<u64>,
+    pub custodians: Option<HashSet<Principal>>,
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub enum TransactionKind {
+    Mint,
+    Transfer,
+    Approve,
+    TransferFrom,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Transaction {
+    pub id: u64,
+    pub kind: TransactionKind,
+    //none for mints
+    pub from: Option<Account>,
+    //none for approvals
+    pub to: Option<Account>,
+    //set for approvals and transfers made on behalf of the owner
+    pub spender: Option<Account>,
+    //the allowance granted, for approvals
+    pub amount_e8s: u64,
+    //burned, not credited to anyone
+    pub fee_e8s: u64,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Subaccount>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Subaccount>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Subaccount>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum MetadataValue {
+    Nat(Nat),
+    Int(Int),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}